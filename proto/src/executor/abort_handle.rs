@@ -0,0 +1,99 @@
+/*
+
+    Handle to cancel a spawned task.
+
+*/
+
+use crate::executor::{ Executor, SpawnedTask, TaskId, TaskSlot };
+
+use std::fmt::{ Debug, Formatter };
+use std::sync::{ Arc, Weak };
+
+
+//------------------------------------------------------------------------------
+//  An owned permission to abort a spawned task.
+//
+//  Aborting drops the future of the task the next time it is scheduled, and
+//  any task awaiting its `JoinHandle` receives `JoinError::Cancelled` . It is
+//  safe to abort from any thread, including from inside another task or from
+//  inside the aborted task itself.
+//------------------------------------------------------------------------------
+#[derive(Clone)]
+pub struct AbortHandle
+{
+    id: TaskId,
+    task: Weak<dyn TaskSlot>,
+    executor: Weak<Executor>,
+}
+
+impl AbortHandle
+{
+    //--------------------------------------------------------------------------
+    //  Creates a new `AbortHandle` .
+    //--------------------------------------------------------------------------
+    pub(crate) fn new( task: &SpawnedTask, executor: Weak<Executor> ) -> Self
+    {
+        Self
+        {
+            id: task.id(),
+            task: Arc::downgrade(task),
+            executor,
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the identifier of the task.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn id( &self ) -> TaskId
+    {
+        self.id
+    }
+
+    //--------------------------------------------------------------------------
+    //  Aborts the task. Does nothing if the task has already completed.
+    //--------------------------------------------------------------------------
+    pub fn abort( &self )
+    {
+        if let Some(task) = self.task.upgrade()
+        {
+            task.abort();
+            match self.executor.upgrade()
+            {
+                Some(executor) => executor.schedule_task(task),
+
+                //  No one polls the task anymore, so drops the future here
+                //  unless it is being polled right now.
+                None => task.drop_future_if_idle(),
+            }
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns true if the task has completed or was aborted and its future
+    //  has been dropped.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn is_finished( &self ) -> bool
+    {
+        match self.task.upgrade()
+        {
+            Some(task) => task.is_finished(),
+            None => true,
+        }
+    }
+}
+
+impl Debug for AbortHandle
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result
+    {
+        write!
+        (
+            f,
+            "AbortHandle{{id={}, finished={:?}}}",
+            self.id,
+            self.is_finished()
+        )
+    }
+}
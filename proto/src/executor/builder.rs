@@ -0,0 +1,451 @@
+/*
+
+    Builder to configure an Executor.
+
+
+    ```rust
+    let executor = wexing::executor::Executor::builder()
+        .async_threads(8)
+        .async_thread_name_fn(|num| format!("api-async-{}", num))
+        .blocking_threads(16)
+        .max_blocking_threads(256)
+        .blocking_keep_alive(std::time::Duration::from_secs(30))
+        .thread_stack_size(1024 * 1024)
+        .on_thread_start(|| println!("thread started"))
+        .on_thread_stop(|| println!("thread stopped"))
+        .before_poll(|id| println!("polling {}", id))
+        .priority_aging_interval(32)
+        .clock(wexing::timer::Clock::system())
+        .build()
+        .unwrap();
+    ```
+
+    Unless set explicitly, the number of threads and the stack size are read
+    from the following environment variables.
+
+    - `WEXING_ASYNC_THREADS`
+    - `WEXING_BLOCKING_THREADS`
+    - `WEXING_THREAD_STACK_SIZE`
+
+*/
+
+use crate::executor::{ Executor, PanicHandler, PollHook, TaskId };
+use crate::threadpool::{ ThreadHook, ThreadNameFn, ThreadPoolBuilder };
+use crate::threadpool::error::NewThreadPoolError;
+use crate::timer::Clock;
+
+use core::any::Any;
+use core::fmt::{ Debug, Formatter };
+use core::time::Duration;
+use std::sync::Arc;
+
+const DEFAULT_ASYNC_THREADS: usize = 4;
+const DEFAULT_BLOCKING_THREADS: usize = 4;
+const DEFAULT_MAX_BLOCKING_THREADS: usize = 64;
+const DEFAULT_BLOCKING_KEEP_ALIVE: Duration = Duration::from_secs(10);
+const DEFAULT_PRIORITY_AGING_INTERVAL: u64 = 64;
+
+const ENV_ASYNC_THREADS: &str = "WEXING_ASYNC_THREADS";
+const ENV_BLOCKING_THREADS: &str = "WEXING_BLOCKING_THREADS";
+const ENV_THREAD_STACK_SIZE: &str = "WEXING_THREAD_STACK_SIZE";
+
+
+//------------------------------------------------------------------------------
+//  Builder for `Executor` .
+//------------------------------------------------------------------------------
+#[derive(Clone)]
+pub struct ExecutorBuilder
+{
+    async_threads: Option<usize>,
+    async_thread_name: String,
+    async_thread_name_fn: Option<Arc<ThreadNameFn>>,
+    blocking_threads: Option<usize>,
+    max_blocking_threads: Option<usize>,
+    blocking_keep_alive: Duration,
+    blocking_queue_capacity: Option<usize>,
+    blocking_thread_name: String,
+    blocking_thread_name_fn: Option<Arc<ThreadNameFn>>,
+    thread_stack_size: Option<usize>,
+    on_thread_start: Option<Arc<ThreadHook>>,
+    on_thread_stop: Option<Arc<ThreadHook>>,
+    before_poll: Option<Arc<PollHook>>,
+    after_poll: Option<Arc<PollHook>>,
+    panic_handler: Option<Arc<PanicHandler>>,
+    priority_aging_interval: u64,
+    clock: Clock,
+}
+
+impl ExecutorBuilder
+{
+    //--------------------------------------------------------------------------
+    //  Creates a new builder.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn new() -> Self
+    {
+        Self
+        {
+            async_threads: None,
+            async_thread_name: "async".to_string(),
+            async_thread_name_fn: None,
+            blocking_threads: None,
+            max_blocking_threads: None,
+            blocking_keep_alive: DEFAULT_BLOCKING_KEEP_ALIVE,
+            blocking_queue_capacity: None,
+            blocking_thread_name: "blocking".to_string(),
+            blocking_thread_name_fn: None,
+            thread_stack_size: None,
+            on_thread_start: None,
+            on_thread_stop: None,
+            before_poll: None,
+            after_poll: None,
+            panic_handler: None,
+            priority_aging_interval: DEFAULT_PRIORITY_AGING_INTERVAL,
+            clock: Clock::system(),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the number of threads to use for executing async tasks.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn async_threads( mut self, num: usize ) -> Self
+    {
+        self.async_threads = Some(num);
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the name prefix of the async threads.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn async_thread_name( mut self, name: impl Into<String> ) -> Self
+    {
+        self.async_thread_name = name.into();
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the function that generates the names of the async threads.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn async_thread_name_fn
+    (
+        mut self,
+        f: impl Fn(usize) -> String + Send + Sync + 'static,
+    ) -> Self
+    {
+        self.async_thread_name_fn = Some(Arc::new(f));
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the number of threads to use for executing blocking jobs.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn blocking_threads( mut self, num: usize ) -> Self
+    {
+        self.blocking_threads = Some(num);
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the maximum number of blocking threads. The blocking pool starts
+    //  more threads than `blocking_threads` when jobs are scheduled while all
+    //  threads are busy. Defaults to 64, or `blocking_threads` if larger.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn max_blocking_threads( mut self, num: usize ) -> Self
+    {
+        self.max_blocking_threads = Some(num);
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets how long a blocking thread above `blocking_threads` stays idle
+    //  before it stops. Defaults to 10 seconds.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn blocking_keep_alive( mut self, keep_alive: Duration ) -> Self
+    {
+        self.blocking_keep_alive = keep_alive;
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the maximum number of queued blocking jobs. When the queue is
+    //  full, `Executor::try_schedule_blocking` returns
+    //  `TryScheduleError::QueueFull` .
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn blocking_queue_capacity( mut self, capacity: usize ) -> Self
+    {
+        self.blocking_queue_capacity = Some(capacity);
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the name prefix of the blocking threads.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn blocking_thread_name( mut self, name: impl Into<String> ) -> Self
+    {
+        self.blocking_thread_name = name.into();
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the function that generates the names of the blocking threads.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn blocking_thread_name_fn
+    (
+        mut self,
+        f: impl Fn(usize) -> String + Send + Sync + 'static,
+    ) -> Self
+    {
+        self.blocking_thread_name_fn = Some(Arc::new(f));
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the stack size in bytes of both async and blocking threads.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn thread_stack_size( mut self, stack_size: usize ) -> Self
+    {
+        self.thread_stack_size = Some(stack_size);
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the function called on each async and blocking thread when it
+    //  starts. Use this to set up thread-local state.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn on_thread_start( mut self, f: impl Fn() + Send + Sync + 'static )
+        -> Self
+    {
+        self.on_thread_start = Some(Arc::new(f));
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the function called on each async and blocking thread when it
+    //  stops.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn on_thread_stop( mut self, f: impl Fn() + Send + Sync + 'static )
+        -> Self
+    {
+        self.on_thread_stop = Some(Arc::new(f));
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the function called on the worker thread before each poll of a
+    //  task.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn before_poll( mut self, f: impl Fn(TaskId) + Send + Sync + 'static )
+        -> Self
+    {
+        self.before_poll = Some(Arc::new(f));
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the function called on the worker thread after each poll of a
+    //  task, including polls that panicked.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn after_poll( mut self, f: impl Fn(TaskId) + Send + Sync + 'static )
+        -> Self
+    {
+        self.after_poll = Some(Arc::new(f));
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the function called when a task panics. See
+    //  `Executor::set_panic_handler` .
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn panic_handler
+    (
+        mut self,
+        f: impl Fn(&(dyn Any + Send)) + Send + Sync + 'static,
+    ) -> Self
+    {
+        self.panic_handler = Some(Arc::new(f));
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets how fast waiting tasks gain priority. A ready task gains one
+    //  priority level each time `interval` tasks are queued after it, so a
+    //  task spawned with `Executor::spawn_with_priority` can delay a task with
+    //  a lower priority by at most `interval` polls per level. With 0, tasks
+    //  run in FIFO order regardless of priority. Defaults to 64.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn priority_aging_interval( mut self, interval: u64 ) -> Self
+    {
+        self.priority_aging_interval = interval;
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the clock read by the timers of the tasks. Use a virtual clock to
+    //  control the time in tests. Defaults to the system clock.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn clock( mut self, clock: Clock ) -> Self
+    {
+        self.clock = clock;
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Creates the executor and starts its threads.
+    //
+    //  Returns `NewThreadPoolError::Parameter` if an environment variable has
+    //  an invalid value.
+    //--------------------------------------------------------------------------
+    pub fn build( self ) -> Result<Arc<Executor>, NewThreadPoolError>
+    {
+        let async_threads = match self.async_threads
+        {
+            Some(num) => num,
+            None => env_usize(ENV_ASYNC_THREADS)?
+                .unwrap_or(DEFAULT_ASYNC_THREADS),
+        };
+        let blocking_threads = match self.blocking_threads
+        {
+            Some(num) => num,
+            None => env_usize(ENV_BLOCKING_THREADS)?
+                .unwrap_or(DEFAULT_BLOCKING_THREADS),
+        };
+        let thread_stack_size = match self.thread_stack_size
+        {
+            Some(size) => Some(size),
+            None => env_usize(ENV_THREAD_STACK_SIZE)?,
+        };
+
+        let async_pool = self.pool_builder
+        (
+            &self.async_thread_name,
+            &self.async_thread_name_fn,
+            async_threads,
+            thread_stack_size,
+        )
+        .build()?;
+        let max_blocking_threads = self
+            .max_blocking_threads
+            .unwrap_or(DEFAULT_MAX_BLOCKING_THREADS.max(blocking_threads));
+        let mut blocking_pool_builder = self.pool_builder
+        (
+            &self.blocking_thread_name,
+            &self.blocking_thread_name_fn,
+            blocking_threads,
+            thread_stack_size,
+        )
+        .max_size(max_blocking_threads)
+        .keep_alive(self.blocking_keep_alive);
+        if let Some(capacity) = self.blocking_queue_capacity
+        {
+            blocking_pool_builder = blocking_pool_builder
+                .queue_capacity(capacity);
+        }
+        let blocking_pool = blocking_pool_builder.build()?;
+
+        Ok(Executor::from_parts
+        (
+            async_pool,
+            blocking_pool,
+            self.panic_handler,
+            self.before_poll,
+            self.after_poll,
+            self.priority_aging_interval,
+            self.clock,
+        ))
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns a `ThreadPoolBuilder` with the thread options of this builder.
+    //--------------------------------------------------------------------------
+    fn pool_builder
+    (
+        &self,
+        name: &str,
+        name_fn: &Option<Arc<ThreadNameFn>>,
+        size: usize,
+        stack_size: Option<usize>,
+    ) -> ThreadPoolBuilder
+    {
+        let mut builder = ThreadPoolBuilder::new().name(name).size(size);
+        if let Some(name_fn) = name_fn.clone()
+        {
+            builder = builder.thread_name_fn(move |num| name_fn(num));
+        }
+        if let Some(stack_size) = stack_size
+        {
+            builder = builder.stack_size(stack_size);
+        }
+        if let Some(on_thread_start) = self.on_thread_start.clone()
+        {
+            builder = builder.on_thread_start(move || on_thread_start());
+        }
+        if let Some(on_thread_stop) = self.on_thread_stop.clone()
+        {
+            builder = builder.on_thread_stop(move || on_thread_stop());
+        }
+        builder
+    }
+}
+
+impl Default for ExecutorBuilder
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl Debug for ExecutorBuilder
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), core::fmt::Error>
+    {
+        write!
+        (
+            f,
+            "ExecutorBuilder{{async_threads={:?}, blocking_threads={:?}, \
+            max_blocking_threads={:?}}}",
+            self.async_threads,
+            self.blocking_threads,
+            self.max_blocking_threads
+        )
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Reads a `usize` from the environment variable `name` . Returns `None` if
+//  the variable is not set.
+//------------------------------------------------------------------------------
+fn env_usize( name: &str ) -> Result<Option<usize>, NewThreadPoolError>
+{
+    match std::env::var(name)
+    {
+        Ok(value) => value.trim().parse().map(Some).map_err(|_|
+        {
+            NewThreadPoolError::Parameter(format!
+            (
+                "{} has an invalid value: {:?}",
+                name,
+                value
+            ))
+        }),
+        Err(_) => Ok(None),
+    }
+}
@@ -0,0 +1,245 @@
+/*
+
+    Cooperative scheduling.
+
+    Each poll of a task gets a budget of operations. The futures of `net` ,
+    `sync` and `timer` consume one unit each time they are ready, and return
+    `Poll::Pending` after waking the task when the budget runs out. A future
+    that is not ready gives its unit back. This makes a task that is always
+    ready, such as a loop draining a channel, yield its worker thread to other
+    tasks.
+
+
+    ```rust
+    use wexing::executor;
+
+    executor::spawn(async
+    {
+        loop
+        {
+            //  Work that never awaits a resource...
+            executor::yield_now().await;
+        }
+    });
+
+    //  Latency-critical tasks can opt out of the budget.
+    executor::spawn(executor::unconstrained(async { /* ... */ }));
+    ```
+
+*/
+
+use core::cell::Cell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{ Context, Poll };
+use std::fmt::{ Debug, Formatter };
+
+//------------------------------------------------------------------------------
+//  Number of operations a task can perform in a single poll.
+//------------------------------------------------------------------------------
+const INITIAL_BUDGET: u8 = 128;
+
+
+//------------------------------------------------------------------------------
+//  Remaining budget of the task being polled on this thread. `None` means the
+//  task is not constrained, which is the case outside of the executor.
+//------------------------------------------------------------------------------
+thread_local!
+{
+    static BUDGET: Cell<Option<u8>> = const { Cell::new(None) };
+}
+
+
+//------------------------------------------------------------------------------
+//  Restores the previous budget when dropped.
+//------------------------------------------------------------------------------
+struct ResetGuard(Option<u8>);
+
+impl Drop for ResetGuard
+{
+    fn drop( &mut self )
+    {
+        BUDGET.with(|cell| cell.set(self.0));
+    }
+}
+
+//------------------------------------------------------------------------------
+//  Sets `budget` while `f` runs.
+//------------------------------------------------------------------------------
+fn with_budget<R>( budget: Option<u8>, f: impl FnOnce() -> R ) -> R
+{
+    let _guard = ResetGuard(BUDGET.with(|cell| cell.replace(budget)));
+    f()
+}
+
+
+//------------------------------------------------------------------------------
+//  Runs `f` , which polls a task, with a new budget.
+//------------------------------------------------------------------------------
+pub(crate) fn budget<R>( f: impl FnOnce() -> R ) -> R
+{
+    with_budget(Some(INITIAL_BUDGET), f)
+}
+
+
+//------------------------------------------------------------------------------
+//  Takes one unit of the budget of the current task. Returns `Poll::Pending`
+//  and wakes the task if the budget has run out.
+//
+//  Resources call this first in `poll` , and call `made_progress` on the
+//  returned guard before returning `Poll::Ready` . Dropping the guard without
+//  it gives the unit back, so only the polls that are ready consume budget.
+//------------------------------------------------------------------------------
+pub(crate) fn poll_proceed( cx: &mut Context<'_> ) -> Poll<RestoreOnPending>
+{
+    BUDGET.with(|cell|
+    {
+        match cell.get()
+        {
+            None => Poll::Ready(RestoreOnPending { taken: Cell::new(false) }),
+            Some(0) =>
+            {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            },
+            Some(remaining) =>
+            {
+                cell.set(Some(remaining - 1));
+                Poll::Ready(RestoreOnPending { taken: Cell::new(true) })
+            },
+        }
+    })
+}
+
+
+//------------------------------------------------------------------------------
+//  Unit of budget taken by `poll_proceed` . It is given back on drop unless
+//  `made_progress` has been called.
+//------------------------------------------------------------------------------
+pub(crate) struct RestoreOnPending
+{
+    taken: Cell<bool>,
+}
+
+impl RestoreOnPending
+{
+    //--------------------------------------------------------------------------
+    //  Keeps the unit consumed. Call this when the resource is ready.
+    //--------------------------------------------------------------------------
+    pub(crate) fn made_progress( &self )
+    {
+        self.taken.set(false);
+    }
+}
+
+impl Drop for RestoreOnPending
+{
+    fn drop( &mut self )
+    {
+        if self.taken.get()
+        {
+            restore_budget();
+        }
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Gives back one unit of the budget of the current task.
+//------------------------------------------------------------------------------
+pub(crate) fn restore_budget()
+{
+    BUDGET.with(|cell|
+    {
+        cell.set(cell.get().map(|remaining| remaining.saturating_add(1)));
+    });
+}
+
+
+//------------------------------------------------------------------------------
+//  Consumes one unit of the budget of the current task, yielding if the budget
+//  has run out. Used by the `async fn` s of resources, which call
+//  `restore_budget` in the same poll when the operation would block.
+//------------------------------------------------------------------------------
+pub(crate) async fn consume_budget()
+{
+    let coop = core::future::poll_fn(poll_proceed).await;
+    coop.made_progress();
+}
+
+
+//------------------------------------------------------------------------------
+//  Yields the worker thread to other tasks. The task is scheduled again
+//  immediately.
+//------------------------------------------------------------------------------
+pub fn yield_now() -> YieldNow
+{
+    YieldNow { yielded: false }
+}
+
+
+//------------------------------------------------------------------------------
+//  Future returned by `yield_now` .
+//------------------------------------------------------------------------------
+#[derive(Debug)]
+pub struct YieldNow
+{
+    yielded: bool,
+}
+
+impl Future for YieldNow
+{
+    type Output = ();
+
+    //--------------------------------------------------------------------------
+    //  Returns `Poll::Pending` and wakes the task at the first poll.
+    //--------------------------------------------------------------------------
+    fn poll( mut self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<()>
+    {
+        if self.yielded
+        {
+            return Poll::Ready(());
+        }
+
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Disables the budget while `fut` is polled. Use this for latency-critical
+//  tasks that must not be forced to yield.
+//------------------------------------------------------------------------------
+pub fn unconstrained<F: Future>( fut: F ) -> Unconstrained<F>
+{
+    Unconstrained { inner: Box::pin(fut) }
+}
+
+
+//------------------------------------------------------------------------------
+//  Future returned by `unconstrained` .
+//------------------------------------------------------------------------------
+pub struct Unconstrained<F: Future>
+{
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for Unconstrained<F>
+{
+    type Output = F::Output;
+
+    fn poll( mut self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<F::Output>
+    {
+        with_budget(None, || self.inner.as_mut().poll(cx))
+    }
+}
+
+impl<F: Future> Debug for Unconstrained<F>
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result
+    {
+        write!(f, "Unconstrained")
+    }
+}
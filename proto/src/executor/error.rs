@@ -0,0 +1,247 @@
+/*
+
+    Errors for Executor.
+
+*/
+
+use crate::executor::TaskId;
+
+use core::any::Any;
+use core::fmt::{ Display, Formatter };
+use std::error::Error;
+use std::io::ErrorKind;
+
+
+//------------------------------------------------------------------------------
+//  JoinError
+//------------------------------------------------------------------------------
+#[derive(Debug)]
+pub enum JoinError
+{
+    //  The task was dropped before it produced a value.
+    Cancelled,
+
+    //  The task panicked. Contains the panic payload.
+    Panic(Box<dyn Any + Send + 'static>),
+}
+
+impl JoinError
+{
+    //--------------------------------------------------------------------------
+    //  Returns true if the task was cancelled.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn is_cancelled( &self ) -> bool
+    {
+        matches!(self, JoinError::Cancelled)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns true if the task panicked.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn is_panic( &self ) -> bool
+    {
+        matches!(self, JoinError::Panic(_))
+    }
+
+    //--------------------------------------------------------------------------
+    //  Consumes the error and returns the panic payload. Panics if the task
+    //  did not panic.
+    //--------------------------------------------------------------------------
+    pub fn into_panic( self ) -> Box<dyn Any + Send + 'static>
+    {
+        match self
+        {
+            JoinError::Panic(payload) => payload,
+            JoinError::Cancelled =>
+            {
+                panic!("expected JoinError::Panic but found {:?}", self)
+            },
+        }
+    }
+}
+
+impl Display for JoinError
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), std::fmt::Error>
+    {
+        match self
+        {
+            JoinError::Cancelled => write!(f, "task was cancelled"),
+            JoinError::Panic(payload) =>
+            {
+                match panic_message(payload.as_ref())
+                {
+                    Some(message) => write!(f, "task panicked: {}", message),
+                    None => write!(f, "task panicked"),
+                }
+            },
+        }
+    }
+}
+
+impl Error for JoinError {}
+
+impl From<JoinError> for std::io::Error
+{
+    fn from( error: JoinError ) -> Self
+    {
+        match error
+        {
+            JoinError::Cancelled =>
+            {
+                std::io::Error::new
+                (
+                    ErrorKind::Interrupted,
+                    "JoinError::Cancelled"
+                )
+            },
+            JoinError::Panic(_) =>
+            {
+                std::io::Error::other(format!("{}", error))
+            },
+        }
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Returns the message of a panic payload if it is a string.
+//------------------------------------------------------------------------------
+pub(crate) fn panic_message( payload: &(dyn Any + Send) ) -> Option<&str>
+{
+    if let Some(s) = payload.downcast_ref::<&'static str>()
+    {
+        Some(s)
+    }
+    else
+    {
+        payload.downcast_ref::<String>().map(String::as_str)
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  ShutdownError
+//------------------------------------------------------------------------------
+#[derive(Debug, PartialEq, Eq)]
+pub enum ShutdownError
+{
+    //  `shutdown` or `shutdown_now` has already been called.
+    AlreadyShutdown,
+
+    //  Tasks or worker threads were still alive at the deadline. Contains the
+    //  identifiers of the tasks that were alive.
+    TimedOut(Vec<TaskId>),
+}
+
+impl Display for ShutdownError
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), std::fmt::Error>
+    {
+        match self
+        {
+            ShutdownError::AlreadyShutdown =>
+            {
+                write!(f, "Executor has already been shut down")
+            },
+            ShutdownError::TimedOut(ids) =>
+            {
+                write!
+                (
+                    f,
+                    "Timed out waiting for Executor to shut down, {} tasks \
+                    were alive",
+                    ids.len()
+                )
+            },
+        }
+    }
+}
+
+impl Error for ShutdownError {}
+
+impl From<ShutdownError> for std::io::Error
+{
+    fn from( error: ShutdownError ) -> Self
+    {
+        match error
+        {
+            ShutdownError::AlreadyShutdown =>
+            {
+                std::io::Error::other("ShutdownError::AlreadyShutdown")
+            },
+            ShutdownError::TimedOut(_) =>
+            {
+                std::io::Error::new(ErrorKind::TimedOut, format!("{}", error))
+            },
+        }
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  AccessError
+//------------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessError;
+
+impl Display for AccessError
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), std::fmt::Error>
+    {
+        write!(f, "Task-local value is not set for the current task")
+    }
+}
+
+impl Error for AccessError {}
+
+impl From<AccessError> for std::io::Error
+{
+    fn from( error: AccessError ) -> Self
+    {
+        std::io::Error::new(ErrorKind::NotFound, format!("{}", error))
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  BlockOnError
+//------------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockOnError
+{
+    //  `block_on` was called from a task running on a worker thread of the
+    //  executor. Blocking the thread could deadlock the executor.
+    NestedBlockOn,
+}
+
+impl Display for BlockOnError
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), std::fmt::Error>
+    {
+        match self
+        {
+            BlockOnError::NestedBlockOn =>
+            {
+                write!
+                (
+                    f,
+                    "block_on called from a worker thread of the executor; \
+                    use block_in_place or spawn instead"
+                )
+            },
+        }
+    }
+}
+
+impl Error for BlockOnError {}
+
+impl From<BlockOnError> for std::io::Error
+{
+    fn from( error: BlockOnError ) -> Self
+    {
+        std::io::Error::other(format!("{}", error))
+    }
+}
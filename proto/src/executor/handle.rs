@@ -0,0 +1,206 @@
+/*
+
+    Handle to an Executor, usable from any thread.
+
+
+    ```rust
+    let executor = wexing::executor::Executor::default();
+    let handle = executor.handle();
+
+    std::thread::spawn(move ||
+    {
+        //  Submits work from a thread that is not part of the executor.
+        let join_handle = handle.spawn(async { 40 + 2 });
+        assert_eq!(handle.block_on(join_handle).unwrap(), 42);
+
+        //  The free functions use the executor of the handle in this scope.
+        let _guard = handle.enter();
+        wexing::executor::spawn(async { println!("spawned") });
+    })
+    .join()
+    .unwrap();
+    ```
+
+*/
+
+use crate::executor::{ get_thread_executor, set_thread_executor, Executor };
+use crate::executor::{ JoinHandle, ThreadExecutorGuard };
+use crate::executor::error::BlockOnError;
+use crate::sync::Receiver;
+use crate::threadpool::error::TryScheduleError;
+
+use core::future::Future;
+use core::marker::PhantomData;
+use std::fmt::{ Debug, Formatter };
+use std::sync::Arc;
+
+
+//------------------------------------------------------------------------------
+//  A cheap cloneable reference to an `Executor` . It keeps the executor alive.
+//------------------------------------------------------------------------------
+#[derive(Clone)]
+pub struct Handle
+{
+    executor: Arc<Executor>,
+}
+
+impl Handle
+{
+    //--------------------------------------------------------------------------
+    //  Creates a new `Handle` .
+    //--------------------------------------------------------------------------
+    pub(crate) fn new( executor: Arc<Executor> ) -> Self
+    {
+        Self { executor }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns a handle to the executor of the current thread.
+    //
+    //  Panics if called from outside a task or an `enter` scope.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn current() -> Self
+    {
+        match Self::try_current()
+        {
+            Some(handle) => handle,
+            None => panic!("Called from outside a task or an `enter` scope."),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns a handle to the executor of the current thread, or `None` if
+    //  called from outside a task or an `enter` scope.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn try_current() -> Option<Self>
+    {
+        get_thread_executor().map(Self::new)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Adds a task that will execute `fut` . See `Executor::spawn` .
+    //--------------------------------------------------------------------------
+    pub fn spawn<T>
+    (
+        &self,
+        fut: impl Future<Output = T> + Send + 'static,
+    ) -> JoinHandle<T>
+    where
+        T: Send + 'static,
+    {
+        self.executor.spawn(fut)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Adds a task with a priority. See `Executor::spawn_with_priority` .
+    //--------------------------------------------------------------------------
+    pub fn spawn_with_priority<T>
+    (
+        &self,
+        level: u8,
+        fut: impl Future<Output = T> + Send + 'static,
+    ) -> JoinHandle<T>
+    where
+        T: Send + 'static,
+    {
+        self.executor.spawn_with_priority(level, fut)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Executes `fut` on the current thread and returns its result. See
+    //  `Executor::block_on` .
+    //--------------------------------------------------------------------------
+    pub fn block_on<R>( &self, fut: impl Future<Output = R> + 'static ) -> R
+    {
+        self.executor.block_on(fut)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Same as `block_on` , but returns an error if called from a task running
+    //  on a worker thread. See `Executor::try_block_on` .
+    //--------------------------------------------------------------------------
+    pub fn try_block_on<R>( &self, fut: impl Future<Output = R> + 'static )
+        -> Result<R, BlockOnError>
+    {
+        self.executor.try_block_on(fut)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Schedules `func` on the blocking threadpool. See
+    //  `Executor::schedule_blocking` .
+    //--------------------------------------------------------------------------
+    pub fn schedule_blocking<T, F>( &self, func: F ) -> Receiver<T>
+    where
+        T: Send + 'static,
+        F: (FnOnce() -> T) + Send + 'static,
+    {
+        self.executor.schedule_blocking(func)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Same as `schedule_blocking` , but returns an error instead of waiting
+    //  when the queue is full. See `Executor::try_schedule_blocking` .
+    //--------------------------------------------------------------------------
+    pub fn try_schedule_blocking<T, F>( &self, func: F )
+        -> Result<Receiver<T>, TryScheduleError>
+    where
+        T: Send + 'static,
+        F: (FnOnce() -> T) + Send + 'static,
+    {
+        self.executor.try_schedule_blocking(func)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the executor as the executor of the current thread until the
+    //  returned guard drops, so that the free functions such as
+    //  `executor::spawn` can be called.
+    //--------------------------------------------------------------------------
+    pub fn enter( &self ) -> EnterGuard<'_>
+    {
+        EnterGuard
+        {
+            _guard: set_thread_executor(Arc::downgrade(&self.executor)),
+            _handle: self,
+            _not_send: PhantomData,
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the executor.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn executor( &self ) -> &Arc<Executor>
+    {
+        &self.executor
+    }
+}
+
+impl Debug for Handle
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result
+    {
+        write!(f, "Handle")
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Guard returned by `Handle::enter` . On drop, it restores the previous
+//  executor of the current thread.
+//------------------------------------------------------------------------------
+pub struct EnterGuard<'a>
+{
+    _guard: ThreadExecutorGuard,
+    _handle: &'a Handle,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a> Debug for EnterGuard<'a>
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result
+    {
+        write!(f, "EnterGuard")
+    }
+}
@@ -0,0 +1,200 @@
+/*
+
+    Handle to await the output of a spawned task.
+
+*/
+
+use crate::executor::{ AbortHandle, TaskId };
+use crate::executor::error::JoinError;
+use crate::sync::{ OneSender, Receiver };
+
+use core::any::Any;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{ Context, Poll };
+use std::fmt::{ Debug, Formatter };
+
+
+//------------------------------------------------------------------------------
+//  An owned permission to await the output of a spawned task.
+//
+//  Awaiting a `JoinHandle` returns the output of the task, or a `JoinError` if
+//  the task panicked or was cancelled. Dropping a `JoinHandle` detaches the
+//  task; the task keeps running but its output is discarded.
+//------------------------------------------------------------------------------
+pub struct JoinHandle<T: Send>
+{
+    receiver: Receiver<Result<T, JoinError>>,
+    abort_handle: AbortHandle,
+}
+
+impl<T: Send> JoinHandle<T>
+{
+    //--------------------------------------------------------------------------
+    //  Creates a new `JoinHandle` .
+    //--------------------------------------------------------------------------
+    pub(crate) fn new
+    (
+        receiver: Receiver<Result<T, JoinError>>,
+        abort_handle: AbortHandle,
+    ) -> Self
+    {
+        Self { receiver, abort_handle }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the identifier of the task.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn id( &self ) -> TaskId
+    {
+        self.abort_handle.id()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Aborts the task. Awaiting this `JoinHandle` returns
+    //  `JoinError::Cancelled` unless the task has already completed.
+    //--------------------------------------------------------------------------
+    pub fn abort( &self )
+    {
+        self.abort_handle.abort();
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns a new `AbortHandle` that can be used to abort the task without
+    //  owning the `JoinHandle` .
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn abort_handle( &self ) -> AbortHandle
+    {
+        self.abort_handle.clone()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns true if the task has completed or was aborted.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn is_finished( &self ) -> bool
+    {
+        self.abort_handle.is_finished()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Detaches the task. The task keeps running until it completes, but its
+    //  output is discarded.
+    //--------------------------------------------------------------------------
+    pub fn detach( self ) {}
+}
+
+impl<T: Send> Future for JoinHandle<T>
+{
+    type Output = Result<T, JoinError>;
+
+    //--------------------------------------------------------------------------
+    //  Returns the output of the task when it completes. If the task was
+    //  dropped without producing a value, returns `JoinError::Cancelled` .
+    //--------------------------------------------------------------------------
+    fn poll( mut self: Pin<&mut Self>, cx: &mut Context<'_> )
+        -> Poll<Self::Output>
+    {
+        match Pin::new(&mut self.receiver).poll(cx)
+        {
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+            Poll::Ready(Err(_)) => Poll::Ready(Err(JoinError::Cancelled)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T: Send> Debug for JoinHandle<T>
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result
+    {
+        write!(f, "JoinHandle<{}>", std::any::type_name::<T>())
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Type-erased future of a spawned task.
+//
+//  The executor calls `panicked` when polling the future panics, so that the
+//  panic payload can be reported to the `JoinHandle` .
+//------------------------------------------------------------------------------
+pub(crate) trait TaskFuture: Future<Output = ()> + Send
+{
+    fn panicked( self: Pin<&mut Self>, payload: Box<dyn Any + Send + 'static> );
+}
+
+
+//------------------------------------------------------------------------------
+//  Future that polls the spawned future and sends its output to the
+//  `JoinHandle` .
+//------------------------------------------------------------------------------
+pub(crate) struct JoinFuture<T: Send, Fut: Future<Output = T>>
+{
+    inner: Fut,
+    sender: Option<OneSender<Result<T, JoinError>>>,
+}
+
+impl<T: Send, Fut: Future<Output = T>> JoinFuture<T, Fut>
+{
+    //--------------------------------------------------------------------------
+    //  Creates a new `JoinFuture` .
+    //--------------------------------------------------------------------------
+    pub(crate) fn new( inner: Fut, sender: OneSender<Result<T, JoinError>> )
+        -> Self
+    {
+        Self
+        {
+            inner,
+            sender: Some(sender),
+        }
+    }
+}
+
+impl<T: Send, Fut: Future<Output = T>> Future for JoinFuture<T, Fut>
+{
+    type Output = ();
+
+    //--------------------------------------------------------------------------
+    //  Polls the inner future and sends its output when it completes.
+    //--------------------------------------------------------------------------
+    fn poll( self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<()>
+    {
+        //  `inner` is pinned with `self` and never moved; `sender` is not
+        //  pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+        match inner.poll(cx)
+        {
+            Poll::Ready(value) =>
+            {
+                if let Some(sender) = this.sender.take()
+                {
+                    let _ = sender.send(Ok(value));
+                }
+                Poll::Ready(())
+            },
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T, Fut> TaskFuture for JoinFuture<T, Fut>
+where
+    T: Send,
+    Fut: Future<Output = T> + Send,
+{
+    //--------------------------------------------------------------------------
+    //  Sends `JoinError::Panic` to the `JoinHandle` .
+    //--------------------------------------------------------------------------
+    fn panicked( self: Pin<&mut Self>, payload: Box<dyn Any + Send + 'static> )
+    {
+        let this = unsafe { self.get_unchecked_mut() };
+        if let Some(sender) = this.sender.take()
+        {
+            let _ = sender.send(Err(JoinError::Panic(payload)));
+        }
+    }
+}
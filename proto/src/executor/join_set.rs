@@ -0,0 +1,321 @@
+/*
+
+    Collection of spawned tasks.
+
+
+    ```rust
+    use wexing::executor::{ self, JoinSet };
+
+    executor::block_on(async
+    {
+        let mut set = JoinSet::new();
+        for backend in 0..4
+        {
+            set.spawn(async move { backend * 10 });
+        }
+
+        //  Awaits the tasks in the order they complete.
+        while let Some(result) = set.join_next().await
+        {
+            println!("{}", result.unwrap());
+        }
+    });
+    ```
+
+    The tasks report their output, panic or cancellation directly to the set.
+    Dropping the set aborts the tasks that are still in it.
+
+*/
+
+use crate::executor::{ get_thread_executor, task_local, AbortHandle, Executor };
+use crate::executor::{ TaskFuture, TaskId, DEFAULT_PRIORITY };
+use crate::executor::error::JoinError;
+
+use core::any::Any;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{ Context, Poll, Waker };
+use std::collections::{ HashMap, VecDeque };
+use std::fmt::{ Debug, Formatter };
+use std::sync::{ Arc, Mutex, Weak };
+
+
+//------------------------------------------------------------------------------
+//  Results reported by the tasks of a `JoinSet` .
+//------------------------------------------------------------------------------
+struct SetState<T>
+{
+    completed: VecDeque<(TaskId, Result<T, JoinError>)>,
+    waker: Option<Waker>,
+}
+
+type SharedState<T> = Arc<Mutex<SetState<T>>>;
+
+fn new_state<T>() -> SharedState<T>
+{
+    Arc::new(Mutex::new(SetState
+    {
+        completed: VecDeque::new(),
+        waker: None,
+    }))
+}
+
+
+//------------------------------------------------------------------------------
+//  A collection of tasks spawned on an `Executor` , awaited in the order they
+//  complete.
+//------------------------------------------------------------------------------
+pub struct JoinSet<T: Send + 'static>
+{
+    state: SharedState<T>,
+    tasks: HashMap<TaskId, AbortHandle>,
+}
+
+impl<T: Send + 'static> JoinSet<T>
+{
+    //--------------------------------------------------------------------------
+    //  Creates an empty set.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn new() -> Self
+    {
+        Self
+        {
+            state: new_state(),
+            tasks: HashMap::new(),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Spawns a task that will execute `fut` on the executor of the current
+    //  task, and adds it to the set.
+    //
+    //  Panics if called from outside a task.
+    //--------------------------------------------------------------------------
+    pub fn spawn( &mut self, fut: impl Future<Output = T> + Send + 'static )
+        -> AbortHandle
+    {
+        match get_thread_executor()
+        {
+            Some(executor) => self.spawn_on(fut, &executor),
+            None => panic!("Called from outside a task; use `spawn_on`."),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Spawns a task that will execute `fut` on `executor` , and adds it to
+    //  the set.
+    //--------------------------------------------------------------------------
+    pub fn spawn_on
+    (
+        &mut self,
+        fut: impl Future<Output = T> + Send + 'static,
+        executor: &Arc<Executor>,
+    ) -> AbortHandle
+    {
+        let id = executor.next_task_id();
+        let fut = task_local::InheritFuture::new(fut, task_local::snapshot());
+        let fut = SetFuture
+        {
+            id,
+            inner: fut,
+            state: Arc::downgrade(&self.state),
+            reported: false,
+        };
+        let abort_handle = executor.spawn_task(id, DEFAULT_PRIORITY, fut);
+        self.tasks.insert(id, abort_handle.clone());
+        abort_handle
+    }
+
+    //--------------------------------------------------------------------------
+    //  Waits for the next task to complete and returns its output, or a
+    //  `JoinError` if it panicked or was cancelled. Returns `None` if the set
+    //  is empty.
+    //--------------------------------------------------------------------------
+    pub async fn join_next( &mut self ) -> Option<Result<T, JoinError>>
+    {
+        core::future::poll_fn(|cx| self.poll_join_next(cx)).await
+    }
+
+    //--------------------------------------------------------------------------
+    //  Polls for the next completed task.
+    //--------------------------------------------------------------------------
+    fn poll_join_next
+    (
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<T, JoinError>>>
+    {
+        let mut state = self.state.lock().unwrap();
+        if let Some((id, result)) = state.completed.pop_front()
+        {
+            self.tasks.remove(&id);
+            return Poll::Ready(Some(result));
+        }
+        if self.tasks.is_empty()
+        {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    //--------------------------------------------------------------------------
+    //  Aborts all the tasks of the set. They stay in the set and `join_next`
+    //  returns `JoinError::Cancelled` for those that had not completed.
+    //--------------------------------------------------------------------------
+    pub fn abort_all( &mut self )
+    {
+        for abort_handle in self.tasks.values()
+        {
+            abort_handle.abort();
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Removes all the tasks from the set without aborting them. Their outputs
+    //  are discarded.
+    //--------------------------------------------------------------------------
+    pub fn detach_all( &mut self )
+    {
+        self.tasks.clear();
+        self.state = new_state();
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the number of tasks in the set, including completed tasks that
+    //  have not been returned by `join_next` .
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn len( &self ) -> usize
+    {
+        self.tasks.len()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns true if the set contains no tasks.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn is_empty( &self ) -> bool
+    {
+        self.tasks.is_empty()
+    }
+}
+
+impl<T: Send + 'static> Default for JoinSet<T>
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl<T: Send + 'static> Drop for JoinSet<T>
+{
+    //--------------------------------------------------------------------------
+    //  Aborts the tasks still in the set.
+    //--------------------------------------------------------------------------
+    fn drop( &mut self )
+    {
+        self.abort_all();
+    }
+}
+
+impl<T: Send + 'static> Debug for JoinSet<T>
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result
+    {
+        write!
+        (
+            f,
+            "JoinSet<{}>{{len={}}}",
+            std::any::type_name::<T>(),
+            self.tasks.len()
+        )
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Future of a task in a `JoinSet` . Reports the output of the task to the
+//  set, or `JoinError::Panic` if it panicked, or `JoinError::Cancelled` if it
+//  is dropped before completing.
+//------------------------------------------------------------------------------
+struct SetFuture<T, Fut>
+{
+    id: TaskId,
+    inner: Fut,
+    state: Weak<Mutex<SetState<T>>>,
+    reported: bool,
+}
+
+impl<T, Fut> SetFuture<T, Fut>
+{
+    //--------------------------------------------------------------------------
+    //  Sends `result` to the set, unless the set was dropped or detached the
+    //  task.
+    //--------------------------------------------------------------------------
+    fn report( &mut self, result: Result<T, JoinError> )
+    {
+        self.reported = true;
+        if let Some(state) = self.state.upgrade()
+        {
+            let mut state = state.lock().unwrap();
+            state.completed.push_back((self.id, result));
+            let waker = state.waker.take();
+            drop(state);
+            if let Some(waker) = waker
+            {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T, Fut: Future<Output = T>> Future for SetFuture<T, Fut>
+{
+    type Output = ();
+
+    fn poll( self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<()>
+    {
+        //  `inner` is pinned with `self` and never moved; the other fields are
+        //  not pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+        match inner.poll(cx)
+        {
+            Poll::Ready(value) =>
+            {
+                this.report(Ok(value));
+                Poll::Ready(())
+            },
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T, Fut> TaskFuture for SetFuture<T, Fut>
+where
+    T: Send,
+    Fut: Future<Output = T> + Send,
+{
+    fn panicked( self: Pin<&mut Self>, payload: Box<dyn Any + Send + 'static> )
+    {
+        let this = unsafe { self.get_unchecked_mut() };
+        this.report(Err(JoinError::Panic(payload)));
+    }
+}
+
+impl<T, Fut> Drop for SetFuture<T, Fut>
+{
+    //--------------------------------------------------------------------------
+    //  Reports the task as cancelled if it is dropped before completing.
+    //--------------------------------------------------------------------------
+    fn drop( &mut self )
+    {
+        if !self.reported
+        {
+            self.report(Err(JoinError::Cancelled));
+        }
+    }
+}
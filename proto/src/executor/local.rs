@@ -0,0 +1,523 @@
+/*
+
+    Single-threaded executor for futures that are not `Send` .
+
+
+    ```rust
+    use std::rc::Rc;
+
+    let local = wexing::executor::LocalExecutor::new();
+    let shared = Rc::new(42);
+    let result = local.block_on(async move
+    {
+        let shared_clone = shared.clone();
+        let handle = wexing::executor::spawn_local(async move
+        {
+            *shared_clone + 1
+        });
+        handle.await.unwrap()
+    });
+    assert_eq!(result, 43);
+    ```
+
+*/
+
+use crate::executor::coop;
+use crate::executor::error::JoinError;
+
+use core::cell::{ Cell, RefCell };
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::task::{ Context, Poll, Waker };
+use std::collections::{ HashMap, VecDeque };
+use std::fmt::{ Debug, Formatter };
+use std::panic::{ catch_unwind, AssertUnwindSafe };
+use std::rc::{ Rc, Weak };
+use std::sync::{ Arc, Mutex };
+use std::thread::Thread;
+
+//------------------------------------------------------------------------------
+//  Identifier used in the ready queue for the future passed to `block_on` .
+//------------------------------------------------------------------------------
+const MAIN_TASK: usize = usize::MAX;
+
+type LocalTask = Pin<Box<dyn Future<Output = ()> + 'static>>;
+
+
+//------------------------------------------------------------------------------
+//  Thread local executor.
+//------------------------------------------------------------------------------
+thread_local!
+{
+    static LOCAL_EXECUTOR: RefCell<Weak<LocalInner>> =
+        const { RefCell::new(Weak::new()) };
+}
+
+
+//------------------------------------------------------------------------------
+//  Creates a new task on the `LocalExecutor` running on the current thread.
+//
+//  Panics if called from outside `LocalExecutor::block_on` .
+//------------------------------------------------------------------------------
+pub fn spawn_local<T: 'static>
+(
+    fut: impl Future<Output = T> + 'static,
+) -> LocalJoinHandle<T>
+{
+    match LOCAL_EXECUTOR.with(|cell| cell.borrow().upgrade())
+    {
+        Some(inner) => LocalInner::spawn(&inner, fut),
+        None =>
+        {
+            panic!("Called from outside a LocalExecutor::block_on.");
+        },
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Data shared with the wakers, which may be called from any thread.
+//------------------------------------------------------------------------------
+struct Shared
+{
+    ready: Mutex<VecDeque<usize>>,
+    thread: Mutex<Option<Thread>>,
+}
+
+impl Shared
+{
+    //--------------------------------------------------------------------------
+    //  Queues the task `id` and unparks the thread running `block_on` .
+    //--------------------------------------------------------------------------
+    fn schedule( &self, id: usize )
+    {
+        self.ready.lock().unwrap().push_back(id);
+        if let Some(thread) = self.thread.lock().unwrap().as_ref()
+        {
+            thread.unpark();
+        }
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Waker that queues a local task.
+//------------------------------------------------------------------------------
+struct LocalTaskWaker
+{
+    id: usize,
+    shared: Arc<Shared>,
+}
+
+impl std::task::Wake for LocalTaskWaker
+{
+    fn wake( self: Arc<Self> )
+    {
+        self.shared.schedule(self.id);
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Internal data of `LocalExecutor` .
+//------------------------------------------------------------------------------
+struct LocalInner
+{
+    tasks: RefCell<HashMap<usize, LocalTask>>,
+    next_id: Cell<usize>,
+    shared: Arc<Shared>,
+}
+
+impl LocalInner
+{
+    //--------------------------------------------------------------------------
+    //  Adds a task that will execute `fut` and queues it.
+    //--------------------------------------------------------------------------
+    fn spawn<T: 'static>
+    (
+        self: &Rc<Self>,
+        fut: impl Future<Output = T> + 'static,
+    ) -> LocalJoinHandle<T>
+    {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+
+        let state = Rc::new(RefCell::new(JoinState
+        {
+            output: None,
+            waker: None,
+            finished: false,
+            aborted: false,
+        }));
+        let task = LocalJoinFuture
+        {
+            inner: Box::pin(fut),
+            state: state.clone(),
+        };
+        self.tasks.borrow_mut().insert(id, Box::pin(task));
+        self.shared.schedule(id);
+
+        LocalJoinHandle
+        {
+            id,
+            state,
+            shared: self.shared.clone(),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Polls the task `id` once. The task is taken out of the task map while
+    //  polling so that it can spawn other tasks.
+    //--------------------------------------------------------------------------
+    fn poll_task( &self, id: usize )
+    {
+        let opt_task = self.tasks.borrow_mut().remove(&id);
+        if let Some(mut task) = opt_task
+        {
+            let waker = Waker::from(Arc::new(LocalTaskWaker
+            {
+                id,
+                shared: self.shared.clone(),
+            }));
+            let mut cx = Context::from_waker(&waker);
+            if coop::budget(|| task.as_mut().poll(&mut cx)).is_pending()
+            {
+                self.tasks.borrow_mut().insert(id, task);
+            }
+        }
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Executor that runs tasks on the current thread. Tasks do not need to be
+//  `Send` , so they can hold `Rc` and other thread-unsafe values.
+//
+//  Tasks run only while `block_on` is running. Tasks that are still pending
+//  when `block_on` returns continue at the next call of `block_on` .
+//
+//  Tasks can await timers from `timer` and channels from `sync` , since the
+//  wakers can be called from any thread.
+//------------------------------------------------------------------------------
+pub struct LocalExecutor
+{
+    inner: Rc<LocalInner>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl LocalExecutor
+{
+    //--------------------------------------------------------------------------
+    //  Creates a new local executor.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn new() -> Self
+    {
+        Self
+        {
+            inner: Rc::new(LocalInner
+            {
+                tasks: RefCell::new(HashMap::new()),
+                next_id: Cell::new(0),
+                shared: Arc::new(Shared
+                {
+                    ready: Mutex::new(VecDeque::new()),
+                    thread: Mutex::new(None),
+                }),
+            }),
+            _not_send: PhantomData,
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Adds a task that will execute `fut` on this executor.
+    //
+    //  Use the returned `LocalJoinHandle` to get the output of `fut` .
+    //--------------------------------------------------------------------------
+    pub fn spawn_local<T: 'static>
+    (
+        &self,
+        fut: impl Future<Output = T> + 'static,
+    ) -> LocalJoinHandle<T>
+    {
+        LocalInner::spawn(&self.inner, fut)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the number of tasks that have not completed.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn num_tasks( &self ) -> usize
+    {
+        self.inner.tasks.borrow().len()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Executes the future on the current thread and returns its result,
+    //  running the spawned local tasks while waiting.
+    //--------------------------------------------------------------------------
+    pub fn block_on<R>( &self, fut: impl Future<Output = R> ) -> R
+    {
+        self.block_on_unpin(Box::pin(fut))
+    }
+
+    pub fn block_on_unpin<R>
+    (
+        &self,
+        mut fut: impl Future<Output = R> + Unpin,
+    ) -> R
+    {
+        let shared = &self.inner.shared;
+        let _guard = LocalExecutorGuard::new(&self.inner);
+        shared.schedule(MAIN_TASK);
+
+        let main_waker = Waker::from(Arc::new(LocalTaskWaker
+        {
+            id: MAIN_TASK,
+            shared: shared.clone(),
+        }));
+        let mut cx = Context::from_waker(&main_waker);
+
+        loop
+        {
+            let ready: Vec<usize> =
+                shared.ready.lock().unwrap().drain(..).collect();
+            if ready.is_empty()
+            {
+                std::thread::park();
+                continue;
+            }
+
+            for id in ready
+            {
+                if id == MAIN_TASK
+                {
+                    let result =
+                        coop::budget(|| Pin::new(&mut fut).poll(&mut cx));
+                    if let Poll::Ready(result) = result
+                    {
+                        return result;
+                    }
+                }
+                else
+                {
+                    self.inner.poll_task(id);
+                }
+            }
+        }
+    }
+}
+
+impl Default for LocalExecutor
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl Debug for LocalExecutor
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result
+    {
+        write!(f, "LocalExecutor{{tasks={:?}}}", self.num_tasks())
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Registers the `LocalExecutor` for the current thread while `block_on` is
+//  running, so that the wakers can unpark it and `spawn_local` can find it.
+//------------------------------------------------------------------------------
+struct LocalExecutorGuard
+{
+    shared: Arc<Shared>,
+    previous: Weak<LocalInner>,
+}
+
+impl LocalExecutorGuard
+{
+    fn new( inner: &Rc<LocalInner> ) -> Self
+    {
+        *inner.shared.thread.lock().unwrap() = Some(std::thread::current());
+        let previous = LOCAL_EXECUTOR.with(|cell|
+        {
+            cell.replace(Rc::downgrade(inner))
+        });
+        Self
+        {
+            shared: inner.shared.clone(),
+            previous,
+        }
+    }
+}
+
+impl Drop for LocalExecutorGuard
+{
+    fn drop( &mut self )
+    {
+        *self.shared.thread.lock().unwrap() = None;
+        let previous = core::mem::take(&mut self.previous);
+        LOCAL_EXECUTOR.with(|cell| cell.replace(previous));
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  State shared by a local task and its `LocalJoinHandle` .
+//------------------------------------------------------------------------------
+struct JoinState<T>
+{
+    output: Option<Result<T, JoinError>>,
+    waker: Option<Waker>,
+    finished: bool,
+    aborted: bool,
+}
+
+impl<T> JoinState<T>
+{
+    //--------------------------------------------------------------------------
+    //  Stores the output of the task and wakes the `LocalJoinHandle` .
+    //--------------------------------------------------------------------------
+    fn finish( &mut self, output: Option<Result<T, JoinError>> )
+    {
+        self.output = output;
+        self.finished = true;
+        if let Some(waker) = self.waker.take()
+        {
+            waker.wake();
+        }
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Future that polls the spawned local future and stores its output.
+//------------------------------------------------------------------------------
+struct LocalJoinFuture<T>
+{
+    inner: Pin<Box<dyn Future<Output = T> + 'static>>,
+    state: Rc<RefCell<JoinState<T>>>,
+}
+
+impl<T> Future for LocalJoinFuture<T>
+{
+    type Output = ();
+
+    fn poll( mut self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<()>
+    {
+        if self.state.borrow().aborted
+        {
+            self.state.borrow_mut().finish(Some(Err(JoinError::Cancelled)));
+            return Poll::Ready(());
+        }
+
+        let result = catch_unwind(AssertUnwindSafe(||
+        {
+            self.inner.as_mut().poll(cx)
+        }));
+        let output = match result
+        {
+            Ok(Poll::Ready(value)) => Ok(value),
+            Ok(Poll::Pending) => return Poll::Pending,
+            Err(payload) => Err(JoinError::Panic(payload)),
+        };
+        self.state.borrow_mut().finish(Some(output));
+        Poll::Ready(())
+    }
+}
+
+impl<T> Drop for LocalJoinFuture<T>
+{
+    //--------------------------------------------------------------------------
+    //  Notifies the `LocalJoinHandle` if the task is dropped before it
+    //  completes.
+    //--------------------------------------------------------------------------
+    fn drop( &mut self )
+    {
+        if let Ok(mut state) = self.state.try_borrow_mut()
+        {
+            if !state.finished
+            {
+                state.finish(None);
+            }
+        }
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  An owned permission to await the output of a local task.
+//
+//  Dropping a `LocalJoinHandle` detaches the task.
+//------------------------------------------------------------------------------
+pub struct LocalJoinHandle<T>
+{
+    id: usize,
+    state: Rc<RefCell<JoinState<T>>>,
+    shared: Arc<Shared>,
+}
+
+impl<T> LocalJoinHandle<T>
+{
+    //--------------------------------------------------------------------------
+    //  Aborts the task. The future is dropped the next time the task is
+    //  scheduled, and awaiting this handle returns `JoinError::Cancelled` .
+    //--------------------------------------------------------------------------
+    pub fn abort( &self )
+    {
+        let mut state = self.state.borrow_mut();
+        if !state.finished
+        {
+            state.aborted = true;
+            drop(state);
+            self.shared.schedule(self.id);
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns true if the task has completed or was aborted.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn is_finished( &self ) -> bool
+    {
+        self.state.borrow().finished
+    }
+
+    //--------------------------------------------------------------------------
+    //  Detaches the task. The task keeps running, but its output is discarded.
+    //--------------------------------------------------------------------------
+    pub fn detach( self ) {}
+}
+
+impl<T> Future for LocalJoinHandle<T>
+{
+    type Output = Result<T, JoinError>;
+
+    fn poll( self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<Self::Output>
+    {
+        let mut state = self.state.borrow_mut();
+        if state.finished
+        {
+            match state.output.take()
+            {
+                Some(output) => Poll::Ready(output),
+                None => Poll::Ready(Err(JoinError::Cancelled)),
+            }
+        }
+        else
+        {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> Debug for LocalJoinHandle<T>
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result
+    {
+        write!(f, "LocalJoinHandle<{}>", std::any::type_name::<T>())
+    }
+}
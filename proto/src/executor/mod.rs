@@ -50,19 +50,86 @@
 
 */
 
-use crate::sync::{ self, Receiver };
+pub mod error;
+mod join_handle;
+pub use join_handle::*;
+mod abort_handle;
+pub use abort_handle::*;
+mod builder;
+pub use builder::*;
+mod local;
+pub use local::*;
+mod task_local;
+pub use task_local::{ TaskLocalFuture, TaskLocalKey };
+mod task;
+use task::{ SpawnedTask, TaskCell, TaskSlot };
+mod ready_queue;
+use ready_queue::ReadyQueue;
+pub(crate) mod coop;
+pub use coop::{ unconstrained, yield_now, Unconstrained, YieldNow };
+mod scope;
+pub use scope::*;
+mod join_set;
+pub use join_set::*;
+mod handle;
+pub use handle::*;
+
+use crate::sync::{ self, OneSender, Receiver };
 use crate::threadpool::ThreadPool;
-use crate::threadpool::error::NewThreadPoolError;
+use crate::timer::Clock;
+use crate::threadpool::error::{ NewThreadPoolError, TryScheduleError };
+use crate::util::{ sleep_ms, AtomicCounter };
+use error::{ BlockOnError, ShutdownError };
 
+use core::any::Any;
 use core::cell::Cell;
 use core::future::Future;
+use core::fmt::{ Display, Formatter };
 use core::pin::Pin;
 use core::task::Poll;
+use core::time::Duration;
+use std::collections::HashMap;
+use std::sync::atomic::{ AtomicBool, Ordering };
 use std::sync::mpsc::SyncSender;
-use std::sync::{ Arc, Mutex, Weak };
+use std::sync::{ Arc, Mutex, RwLock, Weak };
+use std::time::Instant;
+
+//------------------------------------------------------------------------------
+//  Function called with the panic payload when a task panics.
+//------------------------------------------------------------------------------
+pub type PanicHandler = dyn Fn(&(dyn Any + Send)) + Send + Sync + 'static;
+
+//------------------------------------------------------------------------------
+//  Function called with the task identifier before or after a task is polled.
+//------------------------------------------------------------------------------
+pub type PollHook = dyn Fn(TaskId) + Send + Sync + 'static;
+
+
+//------------------------------------------------------------------------------
+//  Maximum time `Executor::shutdown_now` waits for worker threads to stop.
+//------------------------------------------------------------------------------
+const SHUTDOWN_NOW_TIMEOUT: Duration = Duration::from_secs(1);
+
+
+//------------------------------------------------------------------------------
+//  Priority of the tasks created with `spawn` .
+//------------------------------------------------------------------------------
+const DEFAULT_PRIORITY: u8 = 0;
+
 
-type SpawnedTask =
-    Arc<Mutex<Option<Box<dyn Future<Output = ()> + Send + Unpin>>>>;
+//------------------------------------------------------------------------------
+//  Identifier of a spawned task, unique within its `Executor` .
+//------------------------------------------------------------------------------
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TaskId(usize);
+
+impl Display for TaskId
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), std::fmt::Error>
+    {
+        write!(f, "{}", self.0)
+    }
+}
 
 
 //------------------------------------------------------------------------------
@@ -74,6 +141,73 @@ thread_local!
 }
 
 
+//------------------------------------------------------------------------------
+//  The executor whose task the current thread is polling as a worker thread,
+//  or null. Blocking such a thread in `block_on` of the same executor could
+//  deadlock it.
+//------------------------------------------------------------------------------
+thread_local!
+{
+    static IN_WORKER: Cell<*const Executor> =
+        const { Cell::new(core::ptr::null()) };
+}
+
+
+//------------------------------------------------------------------------------
+//  Sets the executor whose task the current thread is polling as a worker
+//  thread, or null. Returns a guard that restores the previous value on drop.
+//------------------------------------------------------------------------------
+fn set_in_worker( executor: *const Executor ) -> InWorkerGuard
+{
+    InWorkerGuard(IN_WORKER.with(|cell| cell.replace(executor)))
+}
+
+struct InWorkerGuard(*const Executor);
+
+impl Drop for InWorkerGuard
+{
+    fn drop( &mut self )
+    {
+        IN_WORKER.with(|cell| cell.set(self.0));
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Returns true if the current thread is a worker thread polling a task.
+//------------------------------------------------------------------------------
+fn in_worker() -> bool
+{
+    !IN_WORKER.with(Cell::get).is_null()
+}
+
+
+//------------------------------------------------------------------------------
+//  Returns an error if the current thread is a worker thread polling a task
+//  of the thread-local executor.
+//------------------------------------------------------------------------------
+fn check_block_on() -> Result<(), BlockOnError>
+{
+    let worker_executor = IN_WORKER.with(Cell::get);
+    let thread_executor = EXECUTOR.with(|cell|
+    {
+        let weak = cell.take();
+        let ptr = weak.as_ptr();
+        cell.set(weak);
+        ptr
+    });
+    if !worker_executor.is_null()
+    && core::ptr::eq(worker_executor, thread_executor)
+    {
+        Err(BlockOnError::NestedBlockOn)
+    }
+    else
+    {
+        Ok(())
+    }
+}
+
+
 //------------------------------------------------------------------------------
 //  Gets the `Executor` from thread-local storage.
 //
@@ -96,29 +230,33 @@ pub fn get_thread_executor() -> Option<Arc<Executor>>
 //  Sets `executor` as the `Executor` for the current thread, saving it to
 //  thread-local storage.
 //
-//  Returns a guard struct. When the guard drops, it removes `executor` from
-//  thread-local storage.
+//  Returns a guard struct. When the guard drops, it restores the previous
+//  `Executor` of the current thread, if any.
 //
 //  This is a low-level function.
 //------------------------------------------------------------------------------
 pub fn set_thread_executor( executor: Weak<Executor> ) -> ThreadExecutorGuard
 {
-    EXECUTOR.with(|cell| cell.set(executor));
-    ThreadExecutorGuard {}
+    let previous = EXECUTOR.with(|cell| cell.replace(executor));
+    ThreadExecutorGuard { previous }
 }
 
 
 //------------------------------------------------------------------------------
-//  Guard returned by `set_thread_executor` . On drop, it removes the
+//  Guard returned by `set_thread_executor` . On drop, it restores the previous
 //  thread-local reference to the executor.
 //------------------------------------------------------------------------------
-pub struct ThreadExecutorGuard;
+pub struct ThreadExecutorGuard
+{
+    previous: Weak<Executor>,
+}
 
 impl Drop for ThreadExecutorGuard
 {
     fn drop( &mut self )
     {
-        EXECUTOR.with(std::cell::Cell::take);
+        let previous = core::mem::take(&mut self.previous);
+        EXECUTOR.with(|cell| cell.set(previous));
     }
 }
 
@@ -126,21 +264,44 @@ impl Drop for ThreadExecutorGuard
 //------------------------------------------------------------------------------
 //  Async executor.
 //------------------------------------------------------------------------------
+//
+//  The threadpools become `None` after `shutdown` or `shutdown_now` .
+//------------------------------------------------------------------------------
 pub struct Executor
 {
-    async_pool: ThreadPool,
-    blocking_pool: ThreadPool,
+    async_pool: RwLock<Option<ThreadPool>>,
+    blocking_pool: RwLock<Option<ThreadPool>>,
+    panic_handler: RwLock<Option<Arc<PanicHandler>>>,
+    before_poll: Option<Arc<PollHook>>,
+    after_poll: Option<Arc<PollHook>>,
+    next_task_id: AtomicCounter,
+    tasks: Mutex<HashMap<TaskId, Weak<dyn TaskSlot>>>,
+    is_shutdown: AtomicBool,
+    drop_pending: AtomicBool,
+    ready: Arc<ReadyQueue>,
+    clock: Clock,
 }
 
 impl Executor
 {
     //--------------------------------------------------------------------------
-    //  Creates a new executor with 4 async threads and 4 blocking threads.
+    //  Creates a new executor with 4 async threads and 4 blocking threads,
+    //  unless overridden by the environment variables read by
+    //  `ExecutorBuilder` .
     //--------------------------------------------------------------------------
     #[must_use]
     pub fn default() -> Arc<Self>
     {
-        Self::new(4, 4).unwrap()
+        Self::builder().build().unwrap()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns an `ExecutorBuilder` to configure a new executor.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn builder() -> ExecutorBuilder
+    {
+        ExecutorBuilder::new()
     }
 
     //--------------------------------------------------------------------------
@@ -176,26 +337,97 @@ impl Executor
         num_blocking_threads: usize,
     ) -> Result<Arc<Self>, NewThreadPoolError>
     {
-        Ok(Arc::new(Self
+        Self::builder()
+            .async_thread_name(async_threads_name)
+            .async_threads(num_async_threads)
+            .blocking_thread_name(blocking_threads_name)
+            .blocking_threads(num_blocking_threads)
+            .build()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Creates a new executor from the threadpools and the hooks.
+    //--------------------------------------------------------------------------
+    fn from_parts
+    (
+        async_pool: ThreadPool,
+        blocking_pool: ThreadPool,
+        panic_handler: Option<Arc<PanicHandler>>,
+        before_poll: Option<Arc<PollHook>>,
+        after_poll: Option<Arc<PollHook>>,
+        priority_aging_interval: u64,
+        clock: Clock,
+    ) -> Arc<Self>
+    {
+        Arc::new(Self
         {
-            async_pool: ThreadPool::new
+            async_pool: RwLock::new(Some(async_pool)),
+            blocking_pool: RwLock::new(Some(blocking_pool)),
+            panic_handler: RwLock::new(panic_handler),
+            before_poll,
+            after_poll,
+            next_task_id: AtomicCounter::new(),
+            tasks: Mutex::new(HashMap::new()),
+            is_shutdown: AtomicBool::new(false),
+            drop_pending: AtomicBool::new(false),
+            ready: Arc::new(ReadyQueue::new
             (
-                async_threads_name,
-                num_async_threads
-            )?,
-            blocking_pool: ThreadPool::new
-            (
-                blocking_threads_name,
-                num_blocking_threads,
-            )?,
-        }))
+                priority_aging_interval,
+                clock.clone(),
+            )),
+            clock,
+        })
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the clock read by the timers of the tasks.
+    //--------------------------------------------------------------------------
+    pub fn clock( &self ) -> &Clock
+    {
+        &self.clock
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the function called when a task panics.
+    //
+    //  The panic is caught, the worker thread keeps running, and the awaiting
+    //  `JoinHandle` returns `JoinError::Panic` . The handler runs on the worker
+    //  thread before the `JoinHandle` is notified, so it can log the payload,
+    //  ignore it, or call `std::process::abort()` to stop the process.
+    //
+    //  Without a handler, the panic is ignored after the panic hook reports it.
+    //--------------------------------------------------------------------------
+    pub fn set_panic_handler
+    (
+        &self,
+        handler: impl Fn(&(dyn Any + Send)) + Send + Sync + 'static,
+    )
+    {
+        *self.panic_handler.write().unwrap() = Some(Arc::new(handler));
+    }
+
+    //--------------------------------------------------------------------------
+    //  Calls the panic handler with `payload` .
+    //--------------------------------------------------------------------------
+    fn handle_panic( &self, payload: &(dyn Any + Send) )
+    {
+        let handler = self.panic_handler.read().unwrap().clone();
+        if let Some(handler) = handler
+        {
+            handler(payload);
+        }
     }
 
     //--------------------------------------------------------------------------
     //  Schedules a job to run on any available thread in blocking threadpool.
     //
     //  Use the returned receiver to get the result of the job.
-    //  If the job panic, the receiver returns `RecvError` .
+    //  If the job panic, or the executor is shut down before the job runs, the
+    //  receiver returns `RecvError` .
+    //
+    //  When the queue of the blocking threadpool is full, blocks the current
+    //  thread until a blocking thread takes a job. Use `try_schedule_blocking`
+    //  to never block.
     //--------------------------------------------------------------------------
     pub fn schedule_blocking<T, F>( self: &Arc<Self>, func: F ) -> Receiver<T>
     where
@@ -203,218 +435,714 @@ impl Executor
         F: (FnOnce() -> T) + Send + 'static,
     {
         let (sender, receiver) = sync::oneshot();
-        let weak_self = Arc::downgrade(self);
-        self.blocking_pool.schedule(move ||
+        if self.is_shutdown()
         {
-            let _guard = set_thread_executor(weak_self);
-            let _result = sender.send(func());
-        });
+            return receiver;
+        }
+
+        if let Some(pool) = self.blocking_pool.read().unwrap().as_ref()
+        {
+            pool.schedule(self.blocking_job(func, sender));
+        }
         receiver
     }
 
     //--------------------------------------------------------------------------
-    //  Adds a task that will execute `fut` .
-    //
-    //  The task runs on any available worker thread. The task runs until `fut`
-    //  completes or the Executor is dropped.
+    //  Same as `schedule_blocking` , but returns `TryScheduleError::QueueFull`
+    //  instead of waiting when the queue of the blocking threadpool is full.
     //--------------------------------------------------------------------------
-    pub fn spawn
+    pub fn try_schedule_blocking<T, F>
     (
         self: &Arc<Self>,
-        fut: impl (Future<Output = ()> ) + Send + 'static,
-    )
+        func: F,
+    ) -> Result<Receiver<T>, TryScheduleError>
+    where
+        T: Send + 'static,
+        F: (FnOnce() -> T) + Send + 'static,
     {
-        self.spawn_unpin(Box::pin(fut));
+        let (sender, receiver) = sync::oneshot();
+        if self.is_shutdown()
+        {
+            return Ok(receiver);
+        }
+
+        if let Some(pool) = self.blocking_pool.read().unwrap().as_ref()
+        {
+            pool.try_schedule(self.blocking_job(func, sender))?;
+        }
+        Ok(receiver)
     }
 
-    pub fn spawn_unpin
+    //--------------------------------------------------------------------------
+    //  Returns the job that runs `func` on a blocking thread and sends its
+    //  result to `sender` .
+    //--------------------------------------------------------------------------
+    fn blocking_job<T, F>
     (
         self: &Arc<Self>,
-        fut: impl (Future<Output = ()>) + Send + Unpin + 'static,
-    )
+        func: F,
+        sender: OneSender<T>,
+    ) -> impl FnOnce() + Send + 'static
+    where
+        T: Send + 'static,
+        F: (FnOnce() -> T) + Send + 'static,
     {
-        let task: SpawnedTask = Arc::new(Mutex::new(Some(Box::new(fut))));
         let weak_self = Arc::downgrade(self);
-        self.async_pool.schedule(move || poll_task(task, weak_self));
+
+        //  A paused clock does not auto advance while the job is queued or
+        //  running. The guard is dropped with the job, even if it never runs.
+        let busy_guard = self.clock.enter_busy();
+        move ||
+        {
+            let _busy_guard = busy_guard;
+
+            //  `shutdown_now` drops the jobs that have not started yet.
+            let drop_pending = weak_self
+                .upgrade()
+                .is_none_or(|e| e.drop_pending.load(Ordering::Acquire));
+            if !drop_pending
+            {
+                let _guard = set_thread_executor(weak_self);
+                let _result = sender.send(func());
+            }
+        }
     }
 
     //--------------------------------------------------------------------------
-    //  Executes the future on the current thread and returns its result.
+    //  Returns a `Handle` to submit work to this executor from any thread.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn handle( self: &Arc<Self> ) -> Handle
+    {
+        Handle::new(self.clone())
+    }
+
+    //--------------------------------------------------------------------------
+    //  Adds a task that will execute `fut` .
     //
-    //  `fut` can call `spawn` to create tasks. Those tasks run on the executor
-    //  and will continue even after `fut` completes and this call returns.
+    //  The task runs on any available worker thread. The task runs until `fut`
+    //  completes or the Executor is dropped.
+    //
+    //  Use the returned `JoinHandle` to get the output of `fut` . Dropping the
+    //  `JoinHandle` detaches the task.
     //--------------------------------------------------------------------------
-    pub fn block_on<R>
+    pub fn spawn<T>
     (
         self: &Arc<Self>,
-        fut: impl (Future<Output = R>) + 'static,
-    ) -> R
+        fut: impl (Future<Output = T>) + Send + 'static,
+    ) -> JoinHandle<T>
+    where
+        T: Send + 'static,
     {
-        self.block_on_unpin(Box::pin(fut))
+        let (sender, receiver) = sync::oneshot();
+        let id = self.next_task_id();
+        let fut = task_local::InheritFuture::new(fut, task_local::snapshot());
+        let abort_handle = self.spawn_task
+        (
+            id,
+            DEFAULT_PRIORITY,
+            JoinFuture::new(fut, sender),
+        );
+        JoinHandle::new(receiver, abort_handle)
     }
 
-    pub fn block_on_unpin<R>
+    //--------------------------------------------------------------------------
+    //  Same as `spawn` , but the task runs before the ready tasks with a lower
+    //  priority. Tasks spawned with `spawn` have priority 0.
+    //
+    //  The task keeps its priority each time it is woken. To prevent
+    //  starvation, a ready task gains one priority level each time
+    //  `priority_aging_interval` tasks are queued after it; see
+    //  `ExecutorBuilder::priority_aging_interval` .
+    //--------------------------------------------------------------------------
+    pub fn spawn_with_priority<T>
     (
         self: &Arc<Self>,
-        fut: impl (Future<Output = R>) + Unpin + 'static,
-    ) -> R
+        level: u8,
+        fut: impl Future<Output = T> + Send + 'static,
+    ) -> JoinHandle<T>
+    where
+        T: Send + 'static,
     {
-        let _guard = set_thread_executor(Arc::downgrade(self));
-        block_on_unpin(fut)
+        let (sender, receiver) = sync::oneshot();
+        let id = self.next_task_id();
+        let fut = task_local::InheritFuture::new(fut, task_local::snapshot());
+        let abort_handle = self.spawn_task
+        (
+            id,
+            level,
+            JoinFuture::new(fut, sender),
+        );
+        JoinHandle::new(receiver, abort_handle)
     }
-}
 
-impl Default for Executor
-{
-    fn default() -> Self
+    pub fn spawn_unpin<T>
+    (
+        self: &Arc<Self>,
+        fut: impl (Future<Output = T>) + Send + Unpin + 'static,
+    ) -> JoinHandle<T>
+    where
+        T: Send + 'static,
     {
-        Arc::try_unwrap(Executor::default()).unwrap_or_else(|_| unreachable!())
+        self.spawn(fut)
     }
-}
 
-//------------------------------------------------------------------------------
-//  Schedules `func` to run on any available thread in the blocking threadpool.
-//
-//  Use the returned receiver to get the result of `func` . If `func` panics,
-//  the receiver returns `RecvError` .
-//------------------------------------------------------------------------------
-pub fn schedule_blocking<T, F>( func: F ) -> Receiver<T>
-where
-    T: Send + 'static,
-    F: (FnOnce() -> T) + Send + 'static,
-{
-    if let Some(executor) = get_thread_executor()
-    {
-        executor.schedule_blocking(func)
-    }
-    else
+    //--------------------------------------------------------------------------
+    //  Returns a new task identifier.
+    //--------------------------------------------------------------------------
+    fn next_task_id( &self ) -> TaskId
     {
-        panic!("Called from outside a task; check for duplicate wexing crate.");
+        TaskId(self.next_task_id.next())
     }
-}
-
-
-//------------------------------------------------------------------------------
-//  Creates a new task to execute `fut` and schedules it for immediate
-//  execution.
-//------------------------------------------------------------------------------
-pub fn spawn( fut: impl (Future<Output = ()>) + Send + 'static )
-{
-    spawn_unpin(Box::pin(fut));
-}
 
-pub fn spawn_unpin( fut: impl (Future<Output = ()>) + Send + Unpin + 'static )
-{
-    if let Some(executor) = get_thread_executor()
-    {
-        let task: SpawnedTask = Arc::new(Mutex::new(Some(Box::new(fut))));
-        let executor_weak = Arc::downgrade(&executor);
-        executor
-            .async_pool
-            .schedule(move || poll_task(task, executor_weak));
-    }
-    else
+    //--------------------------------------------------------------------------
+    //  Creates a task that polls `fut` and schedules it.
+    //
+    //  After shutdown, the task is dropped without being polled, which reports
+    //  the task as cancelled.
+    //--------------------------------------------------------------------------
+    fn spawn_task
+    (
+        self: &Arc<Self>,
+        id: TaskId,
+        priority: u8,
+        fut: impl TaskFuture + 'static,
+    ) -> AbortHandle
     {
-        panic!("Called from outside a task; check for duplicate wexing crate.");
-    }
-}
-
+        let task: SpawnedTask =
+            TaskCell::new(id, priority, fut, Arc::downgrade(self));
+        let abort_handle = AbortHandle::new(&task, Arc::downgrade(self));
+        if self.is_shutdown()
+        {
+            drop(task);
+            return abort_handle;
+        }
 
-//------------------------------------------------------------------------------
-//  Executes the future on the current thread and returns its result.
-//------------------------------------------------------------------------------
-pub fn block_on<R>( fut: impl (Future<Output = R>) + 'static ) -> R
-{
-    block_on_unpin(Box::pin(fut))
-}
+        self.tasks.lock().unwrap().insert(id, Arc::downgrade(&task));
+        self.schedule_task(task);
+        abort_handle
+    }
 
-pub fn block_on_unpin<R>
-(
-    mut fut: impl (Future<Output = R>) + Unpin + 'static,
-) -> R
-{
-    struct BlockOnTaskWaker(Mutex<Option<SyncSender<()>>>);
-    impl std::task::Wake for BlockOnTaskWaker
+    //--------------------------------------------------------------------------
+    //  Schedules `task` to be polled on the async threadpool. Does nothing if
+    //  the task is already queued. If the task is being polled, it is polled
+    //  once more after the current poll.
+    //
+    //  If the threadpool has already been shut down, drops the future of the
+    //  task instead.
+    //--------------------------------------------------------------------------
+    pub(crate) fn schedule_task( self: &Arc<Self>, task: SpawnedTask )
     {
-        fn wake( self: Arc<Self> )
+        if task.header().transition_to_scheduled()
         {
-            if let Some(sender) = self.0.lock().unwrap().take()
+            //  A task woken by the running task runs next on the same worker.
+            if let Some(task) = self.ready.push_lifo(task)
             {
-                let _ = sender.send(());
+                self.submit_task(task);
             }
         }
+        else if self.async_pool.read().unwrap().is_none()
+        {
+            task.drop_future_if_idle();
+            self.remove_task(task.id());
+        }
     }
 
-    loop
+    //--------------------------------------------------------------------------
+    //  Queues a `SCHEDULED` task in the ready queue, and sends a job to the
+    //  async threadpool to poll the next ready task. Does not wait while the
+    //  job queue is full: a worker polls the task after its current one.
+    //--------------------------------------------------------------------------
+    fn submit_task( self: &Arc<Self>, task: SpawnedTask )
     {
-        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
-        let waker = std::task::Waker::from
-        (
-            Arc::new(BlockOnTaskWaker(Mutex::new(Some(sender))))
-        );
-        let mut cx = std::task::Context::from_waker(&waker);
-        if let Poll::Ready(result) = Pin::new(&mut fut).poll(&mut cx)
+        match self.async_pool.read().unwrap().as_ref()
         {
-            return result;
+            Some(pool) =>
+            {
+                self.ready.push(task);
+                self.ready.owe_pop();
+                match pool.try_schedule_runnable(self.ready.clone())
+                {
+                    Err(TryScheduleError::QueueFull) => {},
+                    _ =>
+                    {
+                        self.ready.take_owed_pop();
+                    },
+                }
+            },
+            None =>
+            {
+                task.header().transition_to_complete();
+                task.drop_future_if_idle();
+                self.remove_task(task.id());
+            },
         }
-        receiver.recv().unwrap();
+    }
+
+    //--------------------------------------------------------------------------
+    //  Removes a completed or aborted task from the list of live tasks.
+    //--------------------------------------------------------------------------
+    fn remove_task( &self, id: TaskId )
+    {
+        self.tasks.lock().unwrap().remove(&id);
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the identifiers of the live tasks in ascending order.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn live_tasks( &self ) -> Vec<TaskId>
+    {
+        let mut ids: Vec<TaskId> =
+            self.tasks.lock().unwrap().keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns true if `shutdown` or `shutdown_now` has been called.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn is_shutdown( &self ) -> bool
+    {
+        self.is_shutdown.load(Ordering::Acquire)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Stops accepting new tasks and blocking jobs, and waits until the live
+    //  tasks complete and the queued blocking jobs run, and then joins the
+    //  worker threads.
+    //
+    //  If some tasks are still alive after `timeout` , aborts them and returns
+    //  `ShutdownError::TimedOut` with their identifiers. Also returns
+    //  `ShutdownError::TimedOut` if the worker threads did not stop in time.
+    //
+    //  Do not call this from a task running on this executor; its worker
+    //  thread cannot stop until this call returns.
+    //--------------------------------------------------------------------------
+    pub fn shutdown( self: &Arc<Self>, timeout: Duration )
+        -> Result<(), ShutdownError>
+    {
+        let deadline = Instant::now() + timeout;
+        self.begin_shutdown()?;
+
+        while !self.tasks.lock().unwrap().is_empty()
+            && Instant::now() < deadline
+        {
+            sleep_ms(10);
+        }
+        let alive_tasks = self.live_tasks();
+        self.abort_all_tasks();
+        self.join_pools(deadline, alive_tasks)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Stops accepting new tasks and blocking jobs, aborts all live tasks,
+    //  drops the blocking jobs that have not started, and then joins the
+    //  worker threads.
+    //
+    //  Returns `ShutdownError::TimedOut` if tasks are still being polled or
+    //  worker threads are still running blocking jobs after a short timeout.
+    //--------------------------------------------------------------------------
+    pub fn shutdown_now( self: &Arc<Self> ) -> Result<(), ShutdownError>
+    {
+        let deadline = Instant::now() + SHUTDOWN_NOW_TIMEOUT;
+        self.begin_shutdown()?;
+        self.drop_pending.store(true, Ordering::Release);
+        self.abort_all_tasks();
+
+        while !self.tasks.lock().unwrap().is_empty()
+            && Instant::now() < deadline
+        {
+            sleep_ms(10);
+        }
+        let alive_tasks = self.live_tasks();
+        self.join_pools(deadline, alive_tasks)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Marks the executor as shut down.
+    //--------------------------------------------------------------------------
+    fn begin_shutdown( &self ) -> Result<(), ShutdownError>
+    {
+        if self.is_shutdown.swap(true, Ordering::AcqRel)
+        {
+            return Err(ShutdownError::AlreadyShutdown);
+        }
+        Ok(())
+    }
+
+    //--------------------------------------------------------------------------
+    //  Aborts all live tasks.
+    //--------------------------------------------------------------------------
+    fn abort_all_tasks( self: &Arc<Self> )
+    {
+        let tasks: Vec<SpawnedTask> = self
+            .tasks
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(Weak::upgrade)
+            .collect();
+
+        for task in tasks
+        {
+            task.abort();
+            self.schedule_task(task);
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Takes both threadpools and joins their worker threads by `deadline` .
+    //--------------------------------------------------------------------------
+    fn join_pools
+    (
+        &self,
+        deadline: Instant,
+        alive_tasks: Vec<TaskId>,
+    ) -> Result<(), ShutdownError>
+    {
+        let mut joined = true;
+        for pool_lock in [&self.async_pool, &self.blocking_pool]
+        {
+            match take_pool(pool_lock, deadline)
+            {
+                Some(pool) =>
+                {
+                    let timeout = deadline.saturating_duration_since
+                    (
+                        Instant::now()
+                    );
+                    joined &= pool.try_join(timeout).is_ok();
+                },
+                None => joined = false,
+            }
+        }
+
+        if joined && alive_tasks.is_empty()
+        {
+            Ok(())
+        }
+        else
+        {
+            Err(ShutdownError::TimedOut(alive_tasks))
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Executes the future on the current thread and returns its result.
+    //
+    //  `fut` can call `spawn` to create tasks. Those tasks run on the executor
+    //  and will continue even after `fut` completes and this call returns.
+    //
+    //  Panics if called from a task running on a worker thread; see
+    //  `try_block_on` .
+    //--------------------------------------------------------------------------
+    pub fn block_on<R>
+    (
+        self: &Arc<Self>,
+        fut: impl (Future<Output = R>) + 'static,
+    ) -> R
+    {
+        self.block_on_unpin(Box::pin(fut))
+    }
+
+    pub fn block_on_unpin<R>
+    (
+        self: &Arc<Self>,
+        fut: impl (Future<Output = R>) + Unpin + 'static,
+    ) -> R
+    {
+        match self.try_block_on_unpin(fut)
+        {
+            Ok(result) => result,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Same as `block_on` , but returns `BlockOnError::NestedBlockOn` instead
+    //  of blocking if called from a task running on a worker thread of this
+    //  executor. Wrap the call in `block_in_place` to block such a thread
+    //  safely.
+    //--------------------------------------------------------------------------
+    pub fn try_block_on<R>
+    (
+        self: &Arc<Self>,
+        fut: impl Future<Output = R> + 'static,
+    ) -> Result<R, BlockOnError>
+    {
+        self.try_block_on_unpin(Box::pin(fut))
+    }
+
+    pub fn try_block_on_unpin<R>
+    (
+        self: &Arc<Self>,
+        fut: impl Future<Output = R> + Unpin + 'static,
+    ) -> Result<R, BlockOnError>
+    {
+        let _guard = set_thread_executor(Arc::downgrade(self));
+        check_block_on()?;
+
+        //  The current thread may be a worker of another executor. Its tasks
+        //  must not wait in the LIFO slot while this thread blocks.
+        let _in_worker_guard = set_in_worker(core::ptr::null());
+        ready_queue::flush_lifo();
+        try_block_on_unpin(fut)
+    }
+}
+
+impl Default for Executor
+{
+    fn default() -> Self
+    {
+        Arc::try_unwrap(Executor::default()).unwrap_or_else(|_| unreachable!())
+    }
+}
+
+//------------------------------------------------------------------------------
+//  Schedules `func` to run on any available thread in the blocking threadpool.
+//
+//  Use the returned receiver to get the result of `func` . If `func` panics,
+//  the receiver returns `RecvError` .
+//------------------------------------------------------------------------------
+pub fn schedule_blocking<T, F>( func: F ) -> Receiver<T>
+where
+    T: Send + 'static,
+    F: (FnOnce() -> T) + Send + 'static,
+{
+    if let Some(executor) = get_thread_executor()
+    {
+        executor.schedule_blocking(func)
+    }
+    else
+    {
+        panic!("Called from outside a task; check for duplicate wexing crate.");
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Same as `schedule_blocking` , but returns `TryScheduleError::QueueFull`
+//  instead of waiting when the queue of the blocking threadpool is full.
+//------------------------------------------------------------------------------
+pub fn try_schedule_blocking<T, F>( func: F )
+    -> Result<Receiver<T>, TryScheduleError>
+where
+    T: Send + 'static,
+    F: (FnOnce() -> T) + Send + 'static,
+{
+    if let Some(executor) = get_thread_executor()
+    {
+        executor.try_schedule_blocking(func)
+    }
+    else
+    {
+        panic!("Called from outside a task; check for duplicate wexing crate.");
     }
 }
 
 
 //------------------------------------------------------------------------------
-//  TaskWaker
+//  Creates a new task to execute `fut` and schedules it for immediate
+//  execution.
+//
+//  Use the returned `JoinHandle` to get the output of `fut` .
 //------------------------------------------------------------------------------
-struct TaskWaker
+pub fn spawn<T>( fut: impl (Future<Output = T>) + Send + 'static )
+    -> JoinHandle<T>
+where
+    T: Send + 'static,
 {
-    task: SpawnedTask,
-    executor: Weak<Executor>,
+    if let Some(executor) = get_thread_executor()
+    {
+        executor.spawn(fut)
+    }
+    else
+    {
+        panic!("Called from outside a task; check for duplicate wexing crate.");
+    }
 }
 
-impl TaskWaker
+//------------------------------------------------------------------------------
+//  Same as `spawn` , but the task runs before the ready tasks with a lower
+//  priority. See `Executor::spawn_with_priority` .
+//------------------------------------------------------------------------------
+pub fn spawn_with_priority<T>
+(
+    level: u8,
+    fut: impl Future<Output = T> + Send + 'static,
+) -> JoinHandle<T>
+where
+    T: Send + 'static,
 {
-    pub fn new( task: SpawnedTask, executor: Weak<Executor> ) -> Self
+    if let Some(executor) = get_thread_executor()
+    {
+        executor.spawn_with_priority(level, fut)
+    }
+    else
     {
-        Self{ task, executor }
+        panic!("Called from outside a task; check for duplicate wexing crate.");
     }
 }
 
-impl std::task::Wake for TaskWaker
+pub fn spawn_unpin<T>
+(
+    fut: impl (Future<Output = T>) + Send + Unpin + 'static,
+) -> JoinHandle<T>
+where
+    T: Send + 'static,
 {
-    fn wake( self: Arc<Self> )
+    if let Some(executor) = get_thread_executor()
     {
-        if let Some(ref executor) = self.executor.upgrade()
-        {
-            let task_clone = self.task.clone();
-            let executor_weak = Arc::downgrade(executor);
-            executor
-                .async_pool
-                .schedule(move || poll_task(task_clone, executor_weak));
-        }
+        executor.spawn_unpin(fut)
+    }
+    else
+    {
+        panic!("Called from outside a task; check for duplicate wexing crate.");
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Executes the future on the current thread and returns its result.
+//
+//  Panics if called from a task running on a worker thread; see
+//  `try_block_on` .
+//------------------------------------------------------------------------------
+pub fn block_on<R>( fut: impl (Future<Output = R>) + 'static ) -> R
+{
+    block_on_unpin(Box::pin(fut))
+}
+
+pub fn block_on_unpin<R>
+(
+    fut: impl (Future<Output = R>) + Unpin + 'static,
+) -> R
+{
+    match try_block_on_unpin(fut)
+    {
+        Ok(result) => result,
+        Err(e) => panic!("{}", e),
     }
 }
 
 
-fn poll_task( task: SpawnedTask, executor: Weak<Executor> )
+//------------------------------------------------------------------------------
+//  Same as `block_on` , but returns `BlockOnError::NestedBlockOn` instead of
+//  blocking if called from a task running on a worker thread of the
+//  thread-local executor.
+//------------------------------------------------------------------------------
+pub fn try_block_on<R>( fut: impl Future<Output = R> + 'static )
+    -> Result<R, BlockOnError>
+{
+    try_block_on_unpin(Box::pin(fut))
+}
+
+pub fn try_block_on_unpin<R>
+(
+    mut fut: impl Future<Output = R> + Unpin + 'static,
+) -> Result<R, BlockOnError>
 {
-    if executor.strong_count() > 0
+    check_block_on()?;
+    let clock = crate::timer::current();
+
+    //  Like a woken task, the woken future is counted as busy until it is
+    //  polled again. The count is taken over by the `BusyGuard` of the next
+    //  poll.
+    struct BlockOnTaskWaker(Mutex<Option<(SyncSender<()>, Clock)>>);
+    impl std::task::Wake for BlockOnTaskWaker
+    {
+        fn wake( self: Arc<Self> )
+        {
+            if let Some((sender, clock)) = self.0.lock().unwrap().take()
+            {
+                clock.add_busy();
+                if sender.send(()).is_err()
+                {
+                    clock.sub_busy();
+                }
+            }
+        }
+    }
+
+    let mut busy_guard = clock.enter_busy();
+    loop
     {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        let waker_state = Mutex::new(Some((sender, clock.clone())));
         let waker = std::task::Waker::from
         (
-            Arc::new(TaskWaker::new(task.clone(), executor.clone()))
+            Arc::new(BlockOnTaskWaker(waker_state))
         );
-
         let mut cx = std::task::Context::from_waker(&waker);
-        let mut opt_fut_guard = task.lock().unwrap();
-        if let Some(fut) = opt_fut_guard.as_mut()
+
+        //  A paused clock does not auto advance while the future is polled.
+        if let Poll::Ready(result) =
+            coop::budget(|| Pin::new(&mut fut).poll(&mut cx))
         {
-            let _guard = set_thread_executor(executor);
-            match Pin::new(&mut *fut).poll(&mut cx)
-            {
-                Poll::Ready(()) => { opt_fut_guard.take(); },
-                Poll::Pending => {},
-            }
+            return Ok(result);
         }
+        drop(busy_guard);
+        receiver.recv().unwrap();
+        busy_guard = clock.take_busy();
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Runs `f` , which may block, on the current thread.
+//
+//  When called from a task running on a worker thread, a helper thread takes
+//  over the queued tasks of the executor while `f` runs, and `f` may call
+//  `block_on` . Otherwise, this only calls `f` .
+//
+//  The helper thread of a previous call is reused while it is still alive.
+//------------------------------------------------------------------------------
+pub fn block_in_place<R>( f: impl FnOnce() -> R ) -> R
+{
+    if !in_worker()
+    {
+        return f();
+    }
+
+    //  The task in the LIFO slot would wait for this thread, unseen by the
+    //  helper thread.
+    ready_queue::flush_lifo();
+    let _helper = get_thread_executor().and_then(|executor|
+    {
+        let pool_guard = executor.async_pool.read().unwrap();
+        pool_guard.as_ref().and_then(|pool| pool.start_helper_thread().ok())
+    });
+    let _guard = set_in_worker(core::ptr::null());
+    f()
+}
+
+
+//------------------------------------------------------------------------------
+//  Takes the threadpool out of `pool_lock` . Retries without blocking until
+//  `deadline` , so that threads scheduling jobs on the threadpool never wait
+//  for this call.
+//------------------------------------------------------------------------------
+fn take_pool
+(
+    pool_lock: &RwLock<Option<ThreadPool>>,
+    deadline: Instant,
+) -> Option<ThreadPool>
+{
+    loop
+    {
+        if let Ok(mut guard) = pool_lock.try_write()
+        {
+            return guard.take();
+        }
+
+        if deadline < Instant::now()
+        {
+            return None;
+        }
+
+        sleep_ms(1);
     }
 }
 
@@ -426,6 +1154,8 @@ fn poll_task( task: SpawnedTask, executor: Weak<Executor> )
 mod tests
 {
     use crate::executor;
+    use crate::executor::error::ShutdownError;
+    use core::time::Duration;
 
     #[test]
     fn executor_spawn()
@@ -440,9 +1170,243 @@ mod tests
     }
 
     #[test]
-    fn executor_block_on()
+    fn executor_spawn_join_handle()
     {
-        async fn hello() -> &'static str
+        let executor = executor::Executor::default();
+        let handle = executor.spawn(async { 40 + 2 });
+        let result = executor.block_on(async move { handle.await });
+        assert_eq!(result.unwrap(), 42);
+    }
+
+    #[test]
+    fn executor_spawn_join_handle_nested()
+    {
+        let executor = executor::Executor::default();
+        let result = executor.block_on(async
+        {
+            let handle = executor::spawn(async { "Hello" });
+            handle.await
+        });
+        assert_eq!(result.unwrap(), "Hello");
+    }
+
+    #[test]
+    fn executor_spawn_join_handle_panic()
+    {
+        let executor = executor::Executor::default();
+        let handle = executor.spawn(async { panic!("task panic") });
+        let result = executor.block_on(async move { handle.await });
+        let payload = result.unwrap_err().into_panic();
+        assert_eq!(*payload.downcast::<&str>().unwrap(), "task panic");
+    }
+
+    #[test]
+    fn executor_panic_handler()
+    {
+        let executor = executor::Executor::new(1, 1).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let sender = std::sync::Mutex::new(sender);
+        executor.set_panic_handler(move |payload|
+        {
+            let message = payload.downcast_ref::<&str>().unwrap().to_string();
+            sender.lock().unwrap().send(message).unwrap();
+        });
+
+        let thread_name = || std::thread::current().name().map(String::from);
+        let before = executor.block_on(executor.spawn(async move
+        {
+            thread_name()
+        }));
+        let _ = executor.block_on(executor.spawn(async { panic!("oops") }));
+        let after = executor.block_on(executor.spawn(async move
+        {
+            thread_name()
+        }));
+
+        assert_eq!(receiver.recv().unwrap(), "oops");
+        assert_eq!(before.unwrap(), after.unwrap());
+    }
+
+    #[test]
+    fn executor_builder()
+    {
+        use std::sync::atomic::{ AtomicUsize, Ordering };
+        use std::sync::Arc;
+
+        let started = Arc::new(AtomicUsize::new(0));
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let polled = Arc::new(AtomicUsize::new(0));
+        let started_clone = started.clone();
+        let stopped_clone = stopped.clone();
+        let polled_clone = polled.clone();
+        let executor = executor::Executor::builder()
+            .async_threads(2)
+            .async_thread_name_fn(|num| format!("test-async-{}", num))
+            .blocking_threads(1)
+            .thread_stack_size(256 * 1024)
+            .on_thread_start(move ||
+            {
+                started_clone.fetch_add(1, Ordering::SeqCst);
+            })
+            .on_thread_stop(move ||
+            {
+                stopped_clone.fetch_add(1, Ordering::SeqCst);
+            })
+            .after_poll(move |_id|
+            {
+                polled_clone.fetch_add(1, Ordering::SeqCst);
+            })
+            .build()
+            .unwrap();
+
+        let name = executor.block_on(executor.spawn(async
+        {
+            std::thread::current().name().unwrap().to_string()
+        }));
+        assert!(name.unwrap().starts_with("test-async-"));
+        assert!(polled.load(Ordering::SeqCst) >= 1);
+
+        executor.shutdown(Duration::from_secs(5)).unwrap();
+        assert_eq!(started.load(Ordering::SeqCst), 3);
+        assert_eq!(stopped.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn local_executor()
+    {
+        use std::rc::Rc;
+
+        crate::timer::start_timer_thread();
+        let local = executor::LocalExecutor::new();
+        let counter = Rc::new(core::cell::Cell::new(0));
+        let (sender, receiver) = crate::sync::oneshot();
+        std::thread::spawn(move || sender.send(10).unwrap());
+
+        let counter_clone = counter.clone();
+        let result = local.block_on(async move
+        {
+            let handle = executor::spawn_local(async move
+            {
+                crate::timer::sleep_for(Duration::from_millis(10)).await;
+                counter_clone.set(counter_clone.get() + 1);
+                receiver.await.unwrap()
+            });
+            handle.await.unwrap()
+        });
+        assert_eq!(result, 10);
+        assert_eq!(counter.get(), 1);
+        assert_eq!(local.num_tasks(), 0);
+    }
+
+    #[test]
+    fn local_executor_abort()
+    {
+        let local = executor::LocalExecutor::new();
+        let handle = local.spawn_local(core::future::pending::<()>());
+        handle.abort();
+        let result = local.block_on(handle);
+        assert!(result.unwrap_err().is_cancelled());
+        assert_eq!(local.num_tasks(), 0);
+    }
+
+    #[test]
+    fn executor_shutdown()
+    {
+        let executor = executor::Executor::default();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        executor.spawn(async move
+        {
+            sender.send(()).unwrap();
+        });
+        let blocking = executor.schedule_blocking(|| 42);
+
+        executor.shutdown(Duration::from_secs(5)).unwrap();
+        receiver.recv().unwrap();
+        assert_eq!(blocking.recv().unwrap(), 42);
+        assert!(executor.live_tasks().is_empty());
+        assert_eq!
+        (
+            executor.shutdown(Duration::from_secs(1)),
+            Err(ShutdownError::AlreadyShutdown)
+        );
+
+        let handle = executor.spawn(async { 42 });
+        let result = executor::block_on(handle);
+        assert!(result.unwrap_err().is_cancelled());
+        assert!(executor.schedule_blocking(|| 42).recv().is_err());
+    }
+
+    #[test]
+    fn executor_shutdown_timeout()
+    {
+        let executor = executor::Executor::default();
+
+        //  The sender keeps the waker of the task.
+        let (_sender, receiver) = crate::sync::oneshot::<()>();
+        let handle = executor.spawn(async move { receiver.await });
+        let id = handle.id();
+
+        let result = executor.shutdown(Duration::from_millis(50));
+        assert_eq!(result, Err(ShutdownError::TimedOut(vec![id])));
+        assert!(executor::block_on(handle).unwrap_err().is_cancelled());
+    }
+
+    #[test]
+    fn executor_shutdown_now()
+    {
+        let executor = executor::Executor::default();
+        let handles: Vec<_> = (0..4)
+            .map(|_| executor.spawn(core::future::pending::<()>()))
+            .collect();
+
+        executor.shutdown_now().unwrap();
+        for handle in handles
+        {
+            assert!(executor::block_on(handle).unwrap_err().is_cancelled());
+        }
+    }
+
+    #[test]
+    fn executor_drop_unwakeable_task()
+    {
+        let executor = executor::Executor::new(1, 1).unwrap();
+
+        //  Nothing holds a waker of the task after its first poll.
+        let handle = executor.spawn(core::future::pending::<()>());
+        assert!(executor::block_on(handle).unwrap_err().is_cancelled());
+        assert!(executor.live_tasks().is_empty());
+    }
+
+    #[test]
+    fn executor_abort()
+    {
+        let executor = executor::Executor::default();
+        let handle = executor.spawn(core::future::pending::<()>());
+        handle.abort();
+        let result = executor.block_on(async move { handle.await });
+        assert!(result.unwrap_err().is_cancelled());
+    }
+
+    #[test]
+    fn executor_abort_from_task()
+    {
+        let executor = executor::Executor::default();
+        let result = executor.block_on(async
+        {
+            let handle = executor::spawn(core::future::pending::<()>());
+            let abort_handle = handle.abort_handle();
+            executor::spawn(async move { abort_handle.abort() })
+                .await
+                .unwrap();
+            handle.await
+        });
+        assert!(result.unwrap_err().is_cancelled());
+    }
+
+    #[test]
+    fn executor_block_on()
+    {
+        async fn hello() -> &'static str
         {
             return "Hello";
         }
@@ -458,4 +1422,951 @@ mod tests
             assert_eq!(world().await, "World");
         });
     }
+
+    #[test]
+    fn executor_task_local()
+    {
+        crate::task_local!
+        {
+            static REQUEST_ID: u64;
+        }
+
+        let executor = executor::Executor::default();
+        let (id, inherited) = executor.block_on(REQUEST_ID.scope
+        (
+            42,
+            async
+            {
+                let handle = executor::spawn(async
+                {
+                    REQUEST_ID.try_with(|id| *id).is_ok()
+                });
+                (REQUEST_ID.get(), handle.await.unwrap())
+            }
+        ));
+        assert_eq!((id, inherited), (42, false));
+
+        let child = executor.block_on(REQUEST_ID.scope_inherited
+        (
+            7,
+            async
+            {
+                executor::spawn(async
+                {
+                    let id = REQUEST_ID.get();
+                    executor::spawn(async { REQUEST_ID.get() }).await.unwrap()
+                        + id
+                })
+                .await
+                .unwrap()
+            }
+        ));
+        assert_eq!(child, 14);
+        assert!(REQUEST_ID.try_with(|_| ()).is_err());
+    }
+
+    #[test]
+    fn executor_dedupe_wakeups()
+    {
+        use std::sync::atomic::{ AtomicUsize, Ordering };
+        use std::sync::Arc;
+
+        let executor = executor::Executor::default();
+        let polls = Arc::new(AtomicUsize::new(0));
+        let polls_clone = polls.clone();
+        let handle = executor.spawn(core::future::poll_fn(move |cx|
+        {
+            if polls_clone.fetch_add(1, Ordering::SeqCst) == 0
+            {
+                for _ in 0..5
+                {
+                    cx.waker().wake_by_ref();
+                }
+                return core::task::Poll::Pending;
+            }
+            core::task::Poll::Ready(())
+        }));
+        executor.block_on(async move { handle.await.unwrap() });
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(polls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn executor_waker_points_at_task()
+    {
+        let executor = executor::Executor::default();
+        let mut first_waker: Option<core::task::Waker> = None;
+        let handle = executor.spawn(core::future::poll_fn(move |cx|
+        {
+            match &first_waker
+            {
+                None =>
+                {
+                    first_waker = Some(cx.waker().clone());
+                    cx.waker().wake_by_ref();
+                    core::task::Poll::Pending
+                },
+                Some(waker) =>
+                {
+                    core::task::Poll::Ready(waker.will_wake(cx.waker()))
+                },
+            }
+        }));
+        assert!(executor.block_on(async move { handle.await.unwrap() }));
+    }
+
+    #[test]
+    fn executor_yield_now()
+    {
+        use std::sync::atomic::{ AtomicBool, Ordering };
+        use std::sync::Arc;
+
+        let executor = executor::Executor::builder()
+            .async_threads(1)
+            .build()
+            .unwrap();
+        let flag = Arc::new(AtomicBool::new(false));
+        let flag_clone = flag.clone();
+        let handle = executor.spawn(async move
+        {
+            while !flag_clone.load(Ordering::SeqCst)
+            {
+                executor::yield_now().await;
+            }
+        });
+        executor.spawn(async move { flag.store(true, Ordering::SeqCst) });
+        executor.block_on(async move { handle.await.unwrap() });
+    }
+
+    #[test]
+    fn executor_coop_budget()
+    {
+        use std::sync::atomic::{ AtomicBool, Ordering };
+        use std::sync::Arc;
+
+        let executor = executor::Executor::builder()
+            .async_threads(1)
+            .build()
+            .unwrap();
+        let mutex = Arc::new(crate::sync::Mutex::new(0));
+        let flag = Arc::new(AtomicBool::new(false));
+        let flag_clone = flag.clone();
+
+        //  `lock` is always ready, so the loop only yields when the budget
+        //  runs out.
+        let handle = executor.spawn(async move
+        {
+            while !flag_clone.load(Ordering::SeqCst)
+            {
+                *mutex.lock().await += 1;
+            }
+        });
+        executor.spawn(async move { flag.store(true, Ordering::SeqCst) });
+        executor.block_on(async move { handle.await.unwrap() });
+    }
+
+    #[test]
+    fn executor_coop_budget_pending_is_free()
+    {
+        use core::future::Future;
+        use core::pin::Pin;
+        use core::task::Poll;
+
+        let executor = executor::Executor::builder()
+            .async_threads(1)
+            .build()
+            .unwrap();
+        let handle = executor.spawn(async
+        {
+            let (_sender, mut receiver) = crate::sync::sync_channel::<i32>(1);
+            let mutex = crate::sync::Mutex::new(0);
+
+            //  Polls that return `Poll::Pending` give their unit back, so the
+            //  lock is still ready in the same poll of the task.
+            core::future::poll_fn(|cx|
+            {
+                for _ in 0..1000
+                {
+                    assert!(Pin::new(&mut receiver).poll(cx).is_pending());
+                }
+                let mut lock = Box::pin(mutex.lock());
+                assert!(lock.as_mut().poll(cx).is_ready());
+                Poll::Ready(())
+            }).await;
+        });
+        executor.block_on(handle).unwrap();
+    }
+
+    #[test]
+    fn executor_scope()
+    {
+        let executor = executor::Executor::default();
+        let (total, data) = executor.block_on(async
+        {
+            let mut data = vec![1, 2, 3];
+            let data_ref = &mut data;
+            let total = executor::scope(|s| async move
+            {
+                let (first, rest) = data_ref.split_at_mut(1);
+                let first = s.spawn(async move
+                {
+                    first[0] *= 10;
+                    first[0]
+                });
+                let rest = s.spawn(async move
+                {
+                    executor::yield_now().await;
+                    rest.iter().sum::<i32>()
+                });
+                first.await.unwrap() + rest.await.unwrap()
+            })
+            .await;
+            (total, data)
+        });
+        assert_eq!(total, 15);
+        assert_eq!(data, vec![10, 2, 3]);
+    }
+
+    #[test]
+    fn executor_scope_polls_woken_children()
+    {
+        use core::future::Future;
+        use core::pin::Pin;
+        use std::sync::Arc;
+        use std::sync::atomic::{ AtomicUsize, Ordering };
+
+        const CHILDREN: usize = 50;
+        let executor = executor::Executor::default();
+        let polls = Arc::new(AtomicUsize::new(0));
+        let polls_clone = polls.clone();
+        executor.block_on(async move
+        {
+            let polls = &*polls_clone;
+            executor::scope(|s| async move
+            {
+                let mut senders = Vec::new();
+                for _ in 0..CHILDREN
+                {
+                    let (sender, mut receiver) = crate::sync::oneshot::<()>();
+                    senders.push(sender);
+                    s.spawn(core::future::poll_fn(move |cx|
+                    {
+                        polls.fetch_add(1, Ordering::Relaxed);
+                        Pin::new(&mut receiver).poll(cx).map(|_| ())
+                    }));
+                }
+
+                //  Waking the body does not poll the waiting children.
+                for _ in 0..100
+                {
+                    executor::yield_now().await;
+                }
+                for sender in senders
+                {
+                    sender.send(()).unwrap();
+                }
+            })
+            .await;
+        });
+        assert_eq!(polls.load(Ordering::Relaxed), 2 * CHILDREN);
+    }
+
+    #[test]
+    fn executor_scope_cancel()
+    {
+        let executor = executor::Executor::default();
+        let (sender, receiver) = std::sync::mpsc::channel::<()>();
+        let handle = executor.spawn(executor::scope(|s| async move
+        {
+            s.spawn(async move
+            {
+                let _sender = sender;
+                core::future::pending::<()>().await;
+            });
+            core::future::pending::<()>().await;
+        }));
+        handle.abort();
+        let result = executor.block_on(async move { handle.await });
+        assert!(result.unwrap_err().is_cancelled());
+
+        //  The child was dropped with the scope.
+        assert_eq!
+        (
+            receiver.recv_timeout(Duration::from_secs(1)),
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn executor_join_set()
+    {
+        crate::timer::start_timer_thread();
+        let executor = executor::Executor::default();
+        let results = executor.block_on(async
+        {
+            let mut set = executor::JoinSet::new();
+            set.spawn(async
+            {
+                crate::timer::sleep_for(Duration::from_millis(100)).await;
+                1
+            });
+            set.spawn(async { 2 });
+            set.spawn(async { panic!("join set panic") });
+            assert_eq!(set.len(), 3);
+
+            let mut results = Vec::new();
+            while let Some(result) = set.join_next().await
+            {
+                results.push(result.map_err(|e| e.is_panic()));
+            }
+            assert!(set.is_empty());
+            results
+        });
+        assert_eq!(results.len(), 3);
+        assert!(results.contains(&Err(true)));
+        assert_eq!(results.last(), Some(&Ok(1)));
+    }
+
+    #[test]
+    fn executor_join_set_abort()
+    {
+        let executor = executor::Executor::default();
+        let (sender, receiver) = std::sync::mpsc::channel::<()>();
+        let cancelled = executor.block_on(async move
+        {
+            let mut set = executor::JoinSet::new();
+            for _ in 0..3
+            {
+                set.spawn(core::future::pending::<()>());
+            }
+            set.abort_all();
+            let mut cancelled = 0;
+            while let Some(result) = set.join_next().await
+            {
+                if result.unwrap_err().is_cancelled()
+                {
+                    cancelled += 1;
+                }
+            }
+
+            //  Dropping the set aborts the remaining tasks.
+            set.spawn(async move
+            {
+                let _sender = sender;
+                core::future::pending::<()>().await;
+            });
+            cancelled
+        });
+        assert_eq!(cancelled, 3);
+        assert_eq!
+        (
+            receiver.recv_timeout(Duration::from_secs(1)),
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn executor_handle()
+    {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<executor::Handle>();
+
+        let executor = executor::Executor::default();
+        let handle = executor.handle();
+        let result = std::thread::spawn(move ||
+        {
+            assert!(executor::Handle::try_current().is_none());
+            let join_handle = handle.spawn(async { 40 + 2 });
+            let spawned = handle.block_on(join_handle).unwrap();
+            let blocking = handle.block_on(handle.schedule_blocking(|| 1))
+                .unwrap();
+
+            let _guard = handle.enter();
+            let entered = handle.block_on(executor::spawn(async { 2 }))
+                .unwrap();
+
+            //  `block_on` restores the entered executor when it returns.
+            let entered_again = handle.block_on(executor::spawn(async { 3 }))
+                .unwrap();
+            spawned + blocking + entered + entered_again
+        })
+        .join()
+        .unwrap();
+        assert_eq!(result, 48);
+    }
+
+    #[test]
+    fn executor_nested_block_on()
+    {
+        use crate::executor::error::BlockOnError;
+
+        let executor = executor::Executor::new(1, 1).unwrap();
+        let result = executor.block_on(executor.spawn(async
+        {
+            executor::try_block_on(async { 1 })
+        }));
+        assert_eq!(result.unwrap(), Err(BlockOnError::NestedBlockOn));
+
+        //  The thread calling `block_on` is not a worker thread.
+        assert_eq!(executor.try_block_on(async { 2 }), Ok(2));
+
+        //  A worker thread may block on another executor.
+        let other = executor::Executor::new(1, 1).unwrap();
+        let result = executor.block_on(executor.spawn(async move
+        {
+            other.try_block_on(other.spawn(async { 3 }))
+        }));
+        assert_eq!(result.unwrap().unwrap().unwrap(), 3);
+    }
+
+    #[test]
+    fn executor_block_in_place()
+    {
+        let executor = executor::Executor::new(1, 1).unwrap();
+        let result = executor.block_on(executor.spawn(async
+        {
+            //  The only worker thread blocks while the spawned task runs.
+            executor::block_in_place(||
+            {
+                executor::block_on(executor::spawn(async { 42 })).unwrap()
+            })
+        }));
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(executor::block_in_place(|| 1), 1);
+    }
+
+    #[test]
+    fn executor_block_in_place_reuses_helper()
+    {
+        let executor = executor::Executor::new(1, 1).unwrap();
+        executor.block_on(executor.spawn(async
+        {
+            //  Each helper thread starts waiting for jobs before the next call.
+            for _ in 0..20
+            {
+                executor::block_in_place(||
+                {
+                    std::thread::sleep(Duration::from_millis(5));
+                });
+            }
+        }))
+        .unwrap();
+
+        //  The worker thread and at most one helper thread.
+        let pool_guard = executor.async_pool.read().unwrap();
+        assert!(pool_guard.as_ref().unwrap().num_live_threads() <= 2);
+    }
+
+    #[test]
+    fn executor_block_in_place_lifo_task()
+    {
+        use std::sync::mpsc;
+
+        let executor = executor::Executor::builder()
+            .async_threads(1)
+            .build()
+            .unwrap();
+        let (sender, receiver) = mpsc::channel();
+        executor.spawn(async move
+        {
+            //  The spawned task goes to the LIFO slot of the only worker.
+            let handle = executor::spawn(async { 7 });
+            let result = executor::block_in_place(||
+            {
+                executor::block_on(handle)
+            });
+            sender.send(result.unwrap()).unwrap();
+        });
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(3)), Ok(7));
+    }
+
+    #[test]
+    fn executor_elastic_blocking_pool()
+    {
+        use std::sync::{ Arc, Barrier };
+
+        let executor = executor::Executor::builder()
+            .blocking_threads(1)
+            .max_blocking_threads(3)
+            .blocking_keep_alive(Duration::from_millis(100))
+            .build()
+            .unwrap();
+
+        //  The jobs only complete if they all run at the same time.
+        let barrier = Arc::new(Barrier::new(3));
+        let receivers: Vec<_> = (0..3)
+            .map(|num|
+            {
+                let barrier = barrier.clone();
+                executor.schedule_blocking(move ||
+                {
+                    barrier.wait();
+                    num
+                })
+            })
+            .collect();
+        let total: i32 = receivers
+            .into_iter()
+            .map(|receiver| executor.block_on(receiver).unwrap())
+            .sum();
+        assert_eq!(total, 3);
+
+        //  The threads above `blocking_threads` retire when idle.
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        let num_workers = ||
+        {
+            executor.blocking_pool.read().unwrap().as_ref().unwrap()
+                .num_workers()
+        };
+        while num_workers() > 1 && std::time::Instant::now() < deadline
+        {
+            crate::util::sleep_ms(10);
+        }
+        assert_eq!(num_workers(), 1);
+    }
+
+    #[test]
+    fn executor_try_schedule_blocking_queue_full()
+    {
+        use crate::threadpool::error::TryScheduleError;
+        use std::sync::mpsc;
+
+        let executor = executor::Executor::builder()
+            .blocking_threads(1)
+            .max_blocking_threads(1)
+            .blocking_queue_capacity(1)
+            .build()
+            .unwrap();
+
+        //  Blocks the only thread, then fills the queue.
+        let (started_sender, started_receiver) = mpsc::channel();
+        let (release_sender, release_receiver) = mpsc::channel::<()>();
+        let running = executor.schedule_blocking(move ||
+        {
+            started_sender.send(()).unwrap();
+            release_receiver.recv().unwrap();
+        });
+        started_receiver.recv().unwrap();
+        let queued = executor.try_schedule_blocking(|| 1).unwrap();
+
+        assert_eq!
+        (
+            executor.try_schedule_blocking(|| 2).unwrap_err(),
+            TryScheduleError::QueueFull
+        );
+
+        //  `schedule_blocking` waits for room in the queue.
+        let executor_clone = executor.clone();
+        let waiting = std::thread::spawn(move ||
+        {
+            executor_clone.schedule_blocking(|| 3)
+        });
+
+        release_sender.send(()).unwrap();
+        executor.block_on(running).unwrap();
+        assert_eq!(executor.block_on(queued).unwrap(), 1);
+        let waiting = waiting.join().unwrap();
+        assert_eq!(executor.block_on(waiting).unwrap(), 3);
+    }
+
+    #[test]
+    fn executor_spawn_with_priority()
+    {
+        use std::sync::{ mpsc, Arc, Mutex };
+
+        //  Returns the order in which the tasks ran, after queueing them while
+        //  the only worker thread is blocked.
+        fn run_order( aging_interval: u64, priorities: &[u8] ) -> Vec<usize>
+        {
+            let executor = executor::Executor::builder()
+                .async_threads(1)
+                .priority_aging_interval(aging_interval)
+                .build()
+                .unwrap();
+            let (started_sender, started_receiver) = mpsc::channel();
+            let (release_sender, release_receiver) = mpsc::channel::<()>();
+            let blocker = executor.spawn(async move
+            {
+                started_sender.send(()).unwrap();
+                release_receiver.recv().unwrap();
+            });
+            started_receiver.recv().unwrap();
+
+            let order = Arc::new(Mutex::new(Vec::new()));
+            let handles: Vec<_> = priorities
+                .iter()
+                .enumerate()
+                .map(|(num, &level)|
+                {
+                    let order = order.clone();
+                    executor.spawn_with_priority(level, async move
+                    {
+                        order.lock().unwrap().push(num);
+                    })
+                })
+                .collect();
+
+            release_sender.send(()).unwrap();
+            executor.block_on(blocker).unwrap();
+            for handle in handles
+            {
+                executor.block_on(handle).unwrap();
+            }
+            let order = order.lock().unwrap().clone();
+            order
+        }
+
+        assert_eq!(run_order(64, &[0, 0, 0, 5]), [3, 0, 1, 2]);
+
+        //  Without aging, the task with priority 2 would run first.
+        assert_eq!(run_order(1, &[0, 0, 0, 2]), [0, 1, 3, 2]);
+        assert_eq!(run_order(0, &[0, 0, 0, 5]), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn executor_lifo_slot_fairness()
+    {
+        use std::sync::atomic::{ AtomicUsize, Ordering };
+        use std::sync::{ mpsc, Arc };
+
+        const ROUNDS: usize = 1000;
+
+        let executor = executor::Executor::builder()
+            .async_threads(1)
+            .build()
+            .unwrap();
+        let (started_sender, started_receiver) = mpsc::channel();
+        let (release_sender, release_receiver) = mpsc::channel::<()>();
+        let blocker = executor.spawn(async move
+        {
+            started_sender.send(()).unwrap();
+            release_receiver.recv().unwrap();
+        });
+        started_receiver.recv().unwrap();
+
+        //  The two tasks wake each other, so each one goes to the LIFO slot.
+        let (ping_sender, mut ping_receiver) = crate::sync::sync_channel(1);
+        let (pong_sender, mut pong_receiver) = crate::sync::sync_channel(1);
+        let ponger = executor.spawn(async move
+        {
+            while let Ok(num) = (&mut ping_receiver).await
+            {
+                pong_sender.send(num).unwrap();
+            }
+        });
+        let rounds = Arc::new(AtomicUsize::new(0));
+        let pinger_rounds = rounds.clone();
+        let pinger = executor.spawn(async move
+        {
+            for num in 0..ROUNDS
+            {
+                pinger_rounds.store(num, Ordering::Relaxed);
+                ping_sender.send(num).unwrap();
+                assert_eq!((&mut pong_receiver).await, Ok(num));
+            }
+        });
+        let other = executor.spawn(async move
+        {
+            rounds.load(Ordering::Relaxed)
+        });
+
+        release_sender.send(()).unwrap();
+        executor.block_on(blocker).unwrap();
+        executor.block_on(pinger).unwrap();
+        executor.block_on(ponger).unwrap();
+
+        //  The queued task runs before the ping-pong is over.
+        assert!(executor.block_on(other).unwrap() < ROUNDS / 2);
+    }
+
+    #[test]
+    fn executor_many_timers()
+    {
+        use std::time::Instant;
+
+        crate::timer::start_timer_thread();
+        let executor = executor::Executor::new(2, 1).unwrap();
+        let handles: Vec<_> = (0..1000u64)
+            .map(|num|
+            {
+                executor.spawn(async move
+                {
+                    let deadline =
+                        Instant::now() + Duration::from_millis(num % 100);
+                    crate::timer::sleep_until(deadline).await;
+                    assert!(Instant::now() >= deadline);
+                })
+            })
+            .collect();
+        for handle in handles
+        {
+            executor.block_on(handle).unwrap();
+        }
+    }
+
+    #[test]
+    fn executor_cancel_timers_on_drop()
+    {
+        crate::timer::start_timer_thread();
+        let executor = executor::Executor::new(2, 1).unwrap();
+        let handles: Vec<_> = (0..2000)
+            .map(|_|
+            {
+                executor.spawn(async move
+                {
+                    //  The inner future completes before the deadline.
+                    let result = crate::timer::with_timeout
+                    (
+                        executor::yield_now(),
+                        Duration::from_secs(3600),
+                    ).await;
+                    assert!(result.is_ok());
+
+                    //  The sleep loses the race against the deadline.
+                    let result = crate::timer::with_timeout
+                    (
+                        crate::timer::sleep_for(Duration::from_secs(3600)),
+                        Duration::from_millis(10),
+                    ).await;
+                    assert!(result.is_err());
+                })
+            })
+            .collect();
+        for handle in handles
+        {
+            executor.block_on(handle).unwrap();
+        }
+
+        //  Without cancellation, each task would leave two timers behind.
+        assert!(crate::timer::pending_timers() < 2000);
+    }
+
+    #[test]
+    fn executor_interval()
+    {
+        use crate::stream::Stream;
+        use crate::timer::MissedTickBehavior;
+        use std::time::Instant;
+
+        crate::timer::start_timer_thread();
+        let executor = executor::Executor::new(1, 1).unwrap();
+        let period = Duration::from_millis(50);
+
+        //  Each interval starts 3.5 periods late.
+        let ticks = |behavior|
+        {
+            executor.block_on(async move
+            {
+                let start = Instant::now() - period * 7 / 2;
+                let mut interval = crate::timer::interval_at(start, period);
+                interval.set_missed_tick_behavior(behavior);
+                let mut ticks = Vec::new();
+                for _ in 0..5
+                {
+                    let tick = interval.next().await.unwrap();
+                    ticks.push((tick - start, Instant::now() >= tick));
+                }
+                ticks
+            })
+        };
+
+        //  Catches up with the 4 missed ticks at once.
+        let burst = ticks(MissedTickBehavior::Burst);
+        assert!(burst.iter().all(|&(_, on_time)| on_time));
+        let burst: Vec<_> = burst.into_iter().map(|(tick, _)| tick).collect();
+        assert_eq!(burst, (0..5).map(|num| period * num).collect::<Vec<_>>());
+
+        //  Resumes 1 period after the late tick.
+        let delay = ticks(MissedTickBehavior::Delay);
+        assert!(delay.iter().all(|&(_, on_time)| on_time));
+        assert_eq!(delay[0].0, Duration::ZERO);
+        assert!(delay[1].0 >= period * 9 / 2);
+
+        //  More than 1 period if the tick is polled late again.
+        assert!(delay[2].0 - delay[1].0 >= period);
+
+        //  Resumes at the next tick of the original schedule.
+        let skip = ticks(MissedTickBehavior::Skip);
+        assert!(skip.iter().all(|&(_, on_time)| on_time));
+        assert_eq!(skip[0].0, Duration::ZERO);
+        assert_eq!(skip[1].0, period * 4);
+        assert_eq!(skip[2].0, period * 5);
+    }
+
+    #[test]
+    fn executor_reset_timers()
+    {
+        use crate::timer::{ DeadlineFuture, SleepFuture };
+        use core::future::Future;
+        use core::pin::Pin;
+        use core::task::Poll;
+        use std::time::Instant;
+
+        crate::timer::start_timer_thread();
+        let executor = executor::Executor::new(1, 1).unwrap();
+        let timeout = Duration::from_millis(50);
+
+        //  Each reset pushes the idle timeout forward.
+        let elapsed = executor.block_on(async move
+        {
+            let start = Instant::now();
+            let mut sleep = SleepFuture::new(start + timeout);
+            for _ in 0..3
+            {
+                let result = crate::timer::with_timeout
+                (
+                    &mut sleep,
+                    Duration::from_millis(20),
+                ).await;
+                assert!(result.is_err());
+                sleep.reset(Instant::now() + timeout);
+            }
+            let deadline = sleep.deadline();
+            (&mut sleep).await.unwrap();
+            assert!(Instant::now() >= deadline);
+            start.elapsed()
+        });
+        assert!(elapsed >= Duration::from_millis(110));
+
+        //  The deadline is extended after the wake has been scheduled.
+        let result = executor.block_on(async move
+        {
+            let inner = Box::pin(crate::timer::sleep_for(timeout * 2));
+            let mut deadline =
+                DeadlineFuture::new(inner, Instant::now() + timeout);
+            core::future::poll_fn(|cx|
+            {
+                assert!(Pin::new(&mut deadline).poll(cx).is_pending());
+                Poll::Ready(())
+            }).await;
+            deadline.reset(Instant::now() + timeout * 4);
+            deadline.await
+        });
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn executor_virtual_clock_auto_advance()
+    {
+        use crate::timer::Clock;
+        use std::sync::{ Arc, Mutex };
+        use std::time::Instant;
+
+        let clock = Clock::new_virtual();
+        clock.pause();
+        let executor = executor::Executor::builder()
+            .async_threads(2)
+            .blocking_threads(1)
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        let start = clock.now();
+        let real_start = Instant::now();
+
+        //  The sleeps complete in order at their exact virtual times.
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut handles = Vec::new();
+        for hours in [3, 1, 2]
+        {
+            let order = order.clone();
+            handles.push(executor.spawn(async move
+            {
+                crate::timer::sleep_for(Duration::from_secs(hours * 3600))
+                    .await;
+                let elapsed = crate::timer::now() - start;
+                order.lock().unwrap().push(hours);
+                elapsed
+            }));
+        }
+        for (handle, hours) in handles.into_iter().zip([3, 1, 2])
+        {
+            let elapsed = executor.block_on(handle).unwrap();
+            assert_eq!(elapsed, Duration::from_secs(hours * 3600));
+        }
+        assert_eq!(*order.lock().unwrap(), vec![1, 2, 3]);
+
+        //  A timeout expires although nothing else happens.
+        let result = executor.block_on(async move
+        {
+            crate::timer::with_timeout
+            (
+                core::future::pending::<()>(),
+                Duration::from_secs(60),
+            ).await
+        });
+        assert!(result.is_err());
+        assert_eq!(clock.now() - start, Duration::from_secs(3 * 3600 + 60));
+        assert!(real_start.elapsed() < Duration::from_secs(10));
+        assert_eq!(clock.pending_timers(), 0);
+    }
+
+    #[test]
+    fn executor_virtual_clock_blocking_job()
+    {
+        use crate::timer::Clock;
+
+        let clock = Clock::new_virtual();
+        clock.pause();
+        let executor = executor::Executor::builder()
+            .async_threads(1)
+            .blocking_threads(1)
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+
+        //  The clock does not auto advance while the blocking job runs.
+        let result = executor.block_on(async
+        {
+            crate::timer::with_timeout
+            (
+                executor::schedule_blocking(||
+                {
+                    std::thread::sleep(Duration::from_millis(300));
+                    5
+                }),
+                Duration::from_secs(10),
+            ).await
+        });
+        assert_eq!(result.unwrap().unwrap(), 5);
+    }
+
+    #[test]
+    fn executor_virtual_clock_advance()
+    {
+        use crate::timer::Clock;
+        use std::sync::atomic::{ AtomicBool, Ordering };
+        use std::sync::Arc;
+
+        let clock = Clock::new_virtual();
+        clock.pause();
+        clock.set_auto_advance(false);
+        let executor = executor::Executor::builder()
+            .async_threads(1)
+            .blocking_threads(1)
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+
+        let done = Arc::new(AtomicBool::new(false));
+        let task_done = done.clone();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        executor.spawn(async move
+        {
+            crate::timer::sleep_for(Duration::from_secs(10)).await;
+            task_done.store(true, Ordering::SeqCst);
+            sender.send(()).unwrap();
+        }).detach();
+        while clock.pending_timers() == 0
+        {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        clock.advance(Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!done.load(Ordering::SeqCst));
+
+        clock.advance(Duration::from_secs(5));
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(done.load(Ordering::SeqCst));
+    }
 }
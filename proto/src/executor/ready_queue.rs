@@ -0,0 +1,318 @@
+/*
+
+    Queue of the tasks ready to be polled, ordered by priority.
+
+    Each entry gets a virtual time when it is pushed: the number of entries
+    pushed before it, minus its priority times the aging interval. Entries are
+    popped in ascending virtual time, so a task with a higher priority runs
+    first, and a task that waits gains one priority level for every
+    `aging_interval` tasks pushed after it. Tasks with the same priority run in
+    FIFO order.
+
+    A task woken by the task running on a worker does not go through the
+    queue: it goes to the LIFO slot of the worker and runs next on the same
+    thread, while the data exchanged between the two tasks is still in the
+    cache. After `MAX_LIFO_POLLS` tasks in a row from the slot, the task in the
+    slot is pushed to the queue instead, so two tasks waking each other cannot
+    starve the other tasks.
+
+    Pushing a task never waits for room in the job queue of the threadpool:
+    the workers could end up waiting for each other, and the timer thread
+    would stall every timer. The pop is owed to the queue before the job is
+    sent, and stays owed if the job queue is full. A worker then pops another
+    task after its current one. A job that was sent while its pop was also
+    taken finds the queue empty, which is harmless.
+
+    Each task queued or running is counted as busy on the clock of the
+    executor, so that a paused virtual clock only auto advances when all the
+    tasks are idle. A task taken from the LIFO slot takes over the count of the
+    task that woke it.
+
+*/
+
+use crate::executor::{ in_worker, SpawnedTask };
+use crate::threadpool::Runnable;
+use crate::timer::Clock;
+
+use core::cell::{ Cell, RefCell };
+use core::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::atomic::{ self, AtomicUsize };
+use std::sync::{ Arc, Mutex };
+
+
+//------------------------------------------------------------------------------
+//  Maximum number of tasks a worker runs in a row from its LIFO slot.
+//------------------------------------------------------------------------------
+const MAX_LIFO_POLLS: usize = 3;
+
+
+//------------------------------------------------------------------------------
+//  Queue the current worker thread is running tasks of, and its LIFO slot.
+//------------------------------------------------------------------------------
+thread_local!
+{
+    static CURRENT_QUEUE: Cell<*const ReadyQueue> =
+        const { Cell::new(core::ptr::null()) };
+    static LIFO_SLOT: RefCell<Option<SpawnedTask>> =
+        const { RefCell::new(None) };
+}
+
+
+//------------------------------------------------------------------------------
+//  Sets the queue of the current worker thread while it runs tasks. On drop,
+//  restores the previous queue and reschedules a task left in the LIFO slot.
+//------------------------------------------------------------------------------
+struct CurrentQueueGuard
+{
+    previous: *const ReadyQueue,
+}
+
+impl CurrentQueueGuard
+{
+    fn enter( queue: &ReadyQueue ) -> Self
+    {
+        let previous = CURRENT_QUEUE.with(|current| current.replace(queue));
+        Self { previous }
+    }
+}
+
+impl Drop for CurrentQueueGuard
+{
+    fn drop( &mut self )
+    {
+        CURRENT_QUEUE.with(|current| current.set(self.previous));
+        flush_lifo();
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Removes the task in the LIFO slot of the current thread.
+//------------------------------------------------------------------------------
+fn take_lifo() -> Option<SpawnedTask>
+{
+    LIFO_SLOT.with(|slot| slot.borrow_mut().take())
+}
+
+
+//------------------------------------------------------------------------------
+//  Queues the task in the LIFO slot of the current thread, if any, so that
+//  another worker can run it. Call this before the thread stops running tasks
+//  or blocks.
+//------------------------------------------------------------------------------
+pub(crate) fn flush_lifo()
+{
+    if let Some(task) = take_lifo()
+    {
+        if let Some(executor) = task.header().executor().upgrade()
+        {
+            executor.submit_task(task);
+        }
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Entry of the queue.
+//------------------------------------------------------------------------------
+struct ReadyEntry
+{
+    virtual_time: i128,
+    seq: u64,
+    task: SpawnedTask,
+}
+
+impl Ord for ReadyEntry
+{
+    //--------------------------------------------------------------------------
+    //  `BinaryHeap` pops the greatest entry, which is the entry with the
+    //  smallest virtual time, then the one pushed first.
+    //--------------------------------------------------------------------------
+    fn cmp( &self, other: &Self ) -> Ordering
+    {
+        other.virtual_time
+            .cmp(&self.virtual_time)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for ReadyEntry
+{
+    fn partial_cmp( &self, other: &Self ) -> Option<Ordering>
+    {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ReadyEntry
+{
+    fn eq( &self, other: &Self ) -> bool
+    {
+        self.seq == other.seq
+    }
+}
+
+impl Eq for ReadyEntry {}
+
+
+//------------------------------------------------------------------------------
+//  Entries and the number of entries pushed so far.
+//------------------------------------------------------------------------------
+struct ReadyState
+{
+    heap: BinaryHeap<ReadyEntry>,
+    next_seq: u64,
+}
+
+
+//------------------------------------------------------------------------------
+//  Priority queue of ready tasks. Each job sent to the threadpool for it pops
+//  and polls one task. `owed` counts the tasks pushed without a job.
+//------------------------------------------------------------------------------
+pub(crate) struct ReadyQueue
+{
+    state: Mutex<ReadyState>,
+    aging_interval: u64,
+    owed: AtomicUsize,
+    clock: Clock,
+}
+
+impl ReadyQueue
+{
+    //--------------------------------------------------------------------------
+    //  Creates an empty queue. A waiting task gains one priority level each
+    //  time `aging_interval` tasks are pushed after it. The tasks are counted
+    //  as busy on `clock` .
+    //--------------------------------------------------------------------------
+    pub(crate) fn new( aging_interval: u64, clock: Clock ) -> Self
+    {
+        Self
+        {
+            state: Mutex::new(ReadyState
+            {
+                heap: BinaryHeap::new(),
+                next_seq: 0,
+            }),
+            aging_interval,
+            owed: AtomicUsize::new(0),
+            clock,
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Adds `task` to the queue with its priority.
+    //--------------------------------------------------------------------------
+    pub(crate) fn push( &self, task: SpawnedTask )
+    {
+        let priority = i128::from(task.header().priority());
+        self.clock.add_busy();
+        let mut state = self.state.lock().unwrap();
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        let virtual_time =
+            i128::from(seq) - priority * i128::from(self.aging_interval);
+        state.heap.push(ReadyEntry { virtual_time, seq, task });
+    }
+
+    //--------------------------------------------------------------------------
+    //  Puts `task` in the LIFO slot if it is woken by a task running on a
+    //  worker of this queue. Returns the task that must be pushed to the queue
+    //  instead: `task` itself, or the task it replaced in the slot.
+    //--------------------------------------------------------------------------
+    pub(crate) fn push_lifo( &self, task: SpawnedTask ) -> Option<SpawnedTask>
+    {
+        if !self.is_current()
+        {
+            return Some(task);
+        }
+        LIFO_SLOT.with(|slot| slot.borrow_mut().replace(task))
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns true if the current thread is a worker running the tasks of
+    //  this queue.
+    //--------------------------------------------------------------------------
+    pub(crate) fn is_current( &self ) -> bool
+    {
+        let is_current = CURRENT_QUEUE.with(|current|
+        {
+            core::ptr::eq(current.get(), self)
+        });
+        is_current && in_worker()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Records a task pushed without a job. A worker of this queue pops it
+    //  after its current task.
+    //--------------------------------------------------------------------------
+    pub(crate) fn owe_pop( &self )
+    {
+        self.owed.fetch_add(1, atomic::Ordering::SeqCst);
+    }
+
+    //--------------------------------------------------------------------------
+    //  Takes one of the pops recorded by `owe_pop` , if any.
+    //--------------------------------------------------------------------------
+    pub(crate) fn take_owed_pop( &self ) -> bool
+    {
+        self.owed
+            .fetch_update
+            (
+                atomic::Ordering::SeqCst,
+                atomic::Ordering::SeqCst,
+                |owed| owed.checked_sub(1),
+            )
+            .is_ok()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Removes the next task to poll.
+    //--------------------------------------------------------------------------
+    fn pop( &self ) -> Option<SpawnedTask>
+    {
+        self.state.lock().unwrap().heap.pop().map(|entry| entry.task)
+    }
+}
+
+impl Runnable for ReadyQueue
+{
+    //--------------------------------------------------------------------------
+    //  Polls the next task, then the tasks it puts in the LIFO slot.
+    //
+    //  Each job pops one task. The tasks from the slot have no job; when the
+    //  limit is reached, the task in the slot is pushed and another task is
+    //  popped in its place, so the number of jobs still matches the queue.
+    //  The job also pops the tasks owed to the queue.
+    //--------------------------------------------------------------------------
+    fn run( self: Arc<Self> )
+    {
+        let Some(mut task) = self.pop() else { return };
+        let _guard = CurrentQueueGuard::enter(&self);
+        let mut lifo_polls = 0;
+        loop
+        {
+            task.into_runnable().run();
+            match take_lifo()
+            {
+                Some(next) if lifo_polls < MAX_LIFO_POLLS =>
+                {
+                    lifo_polls += 1;
+                    task = next;
+                    continue;
+                },
+                Some(next) => self.push(next),
+                None if self.take_owed_pop() => {},
+                None =>
+                {
+                    self.clock.sub_busy();
+                    return;
+                },
+            }
+            self.clock.sub_busy();
+            lifo_polls = 0;
+            let Some(popped) = self.pop() else { return };
+            task = popped;
+        }
+    }
+}
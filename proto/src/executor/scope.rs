@@ -0,0 +1,392 @@
+/*
+
+    Structured concurrency scopes.
+
+    `scope` runs a future that can spawn child tasks borrowing from the
+    enclosing stack frame. The scope completes only after the body and every
+    child have completed. Dropping the scope, for example by aborting the task
+    that awaits it, cancels the body and all children; if the body or a child
+    panics, the panic propagates out of the scope and the remaining children
+    are cancelled.
+
+    The children are owned and polled by the scope itself, so they run
+    concurrently with the body on the task that awaits the scope. This is what
+    allows them to borrow: they can never outlive the scope, even if the scope
+    is leaked. They do not run in parallel, though: use `executor::spawn` for
+    work that must use several threads.
+
+    Each child has its own waker, so a wake only polls the child or the body
+    that was woken, whatever the number of children.
+
+
+    ```rust
+    use wexing::executor;
+
+    let data = vec![1, 2, 3];
+    let total = executor::block_on(async move
+    {
+        let data = &data;
+        executor::scope(|s| async move
+        {
+            let first = s.spawn(async move { data[0] });
+            let rest = s.spawn(async move { data[1..].iter().sum::<i32>() });
+            first.await.unwrap() + rest.await.unwrap()
+        })
+        .await
+    });
+    assert_eq!(total, 6);
+    ```
+
+*/
+
+use crate::executor::error::JoinError;
+use crate::sync::{ self, Receiver };
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{ Context, Poll, Waker };
+use std::fmt::{ Debug, Formatter };
+use std::sync::{ Arc, Mutex };
+use std::task::Wake;
+
+type ChildFuture<'env> = Pin<Box<dyn Future<Output = ()> + Send + 'env>>;
+
+
+//------------------------------------------------------------------------------
+//  Index of the body in the woken list of a scope.
+//------------------------------------------------------------------------------
+const BODY: usize = usize::MAX;
+
+
+//------------------------------------------------------------------------------
+//  Runs the future returned by `f` in a new scope. Returns its output after
+//  it and all the tasks spawned with `Scope::spawn` have completed.
+//
+//  The children run on the task that awaits the scope, not in parallel.
+//------------------------------------------------------------------------------
+pub fn scope<'env, R, F, Fut>( f: F ) -> ScopeFuture<'env, R>
+where
+    F: FnOnce(Scope<'env>) -> Fut,
+    Fut: Future<Output = R> + Send + 'env,
+{
+    let wake_state = Arc::new(WakeState
+    {
+        woken: Mutex::new(vec![BODY]),
+        waker: Mutex::new(None),
+    });
+    let inner = Arc::new(ScopeInner
+    {
+        spawned: Mutex::new(Vec::new()),
+        wake_state: wake_state.clone(),
+    });
+    let body = f(Scope { inner: inner.clone() });
+    ScopeFuture
+    {
+        inner,
+        body: Some(Box::pin(body)),
+        body_waker: Waker::from(Arc::new(ChildWaker
+        {
+            wake_state,
+            index: BODY,
+        })),
+        output: None,
+        children: Vec::new(),
+        free: Vec::new(),
+        live: 0,
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  State shared by a `ScopeFuture` and its `Scope` handles.
+//------------------------------------------------------------------------------
+struct ScopeInner<'env>
+{
+    spawned: Mutex<Vec<ChildFuture<'env>>>,
+    wake_state: Arc<WakeState>,
+}
+
+
+//------------------------------------------------------------------------------
+//  Children woken since the last poll of the scope, and the waker of the task
+//  that awaits the scope.
+//------------------------------------------------------------------------------
+struct WakeState
+{
+    woken: Mutex<Vec<usize>>,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl WakeState
+{
+    //--------------------------------------------------------------------------
+    //  Wakes the task that awaits the scope.
+    //--------------------------------------------------------------------------
+    fn wake_scope( &self )
+    {
+        let waker = self.waker.lock().unwrap().clone();
+        if let Some(waker) = waker
+        {
+            waker.wake();
+        }
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Waker of a child, or of the body. Records the index of the child before
+//  waking the scope.
+//------------------------------------------------------------------------------
+struct ChildWaker
+{
+    wake_state: Arc<WakeState>,
+    index: usize,
+}
+
+impl Wake for ChildWaker
+{
+    fn wake( self: Arc<Self> )
+    {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref( self: &Arc<Self> )
+    {
+        self.wake_state.woken.lock().unwrap().push(self.index);
+        self.wake_state.wake_scope();
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Handle to spawn tasks in a scope.
+//------------------------------------------------------------------------------
+pub struct Scope<'env>
+{
+    inner: Arc<ScopeInner<'env>>,
+}
+
+impl<'env> Scope<'env>
+{
+    //--------------------------------------------------------------------------
+    //  Spawns a child task in the scope. `fut` may borrow anything that
+    //  outlives the scope.
+    //
+    //  Use the returned `ScopedJoinHandle` to get the output of `fut` .
+    //  Dropping it detaches the child, which the scope still waits for.
+    //--------------------------------------------------------------------------
+    pub fn spawn<T>
+    (
+        &self,
+        fut: impl Future<Output = T> + Send + 'env,
+    ) -> ScopedJoinHandle<T>
+    where
+        T: Send + 'env,
+    {
+        let (sender, receiver) = sync::oneshot();
+        self.inner.spawned.lock().unwrap().push(Box::pin(async move
+        {
+            let _ = sender.send(fut.await);
+        }));
+
+        self.inner.wake_state.wake_scope();
+        ScopedJoinHandle { receiver }
+    }
+}
+
+impl<'env> Clone for Scope<'env>
+{
+    fn clone( &self ) -> Self
+    {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+impl<'env> Debug for Scope<'env>
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result
+    {
+        write!(f, "Scope")
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Future returned by `scope` . The children are kept in slots with their
+//  wakers; `free` holds the indices of the empty slots and `live` counts the
+//  children that have not completed.
+//------------------------------------------------------------------------------
+pub struct ScopeFuture<'env, R>
+{
+    inner: Arc<ScopeInner<'env>>,
+    body: Option<Pin<Box<dyn Future<Output = R> + Send + 'env>>>,
+    body_waker: Waker,
+    output: Option<R>,
+    children: Vec<Option<(ChildFuture<'env>, Waker)>>,
+    free: Vec<usize>,
+    live: usize,
+}
+
+impl<'env, R> ScopeFuture<'env, R>
+{
+    //--------------------------------------------------------------------------
+    //  Polls the body if it has not completed.
+    //--------------------------------------------------------------------------
+    fn poll_body( &mut self )
+    {
+        let Some(body) = self.body.as_mut() else { return };
+        let mut cx = Context::from_waker(&self.body_waker);
+        if let Poll::Ready(output) = body.as_mut().poll(&mut cx)
+        {
+            self.body = None;
+            self.output = Some(output);
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Polls the child at `index` if it has not completed.
+    //--------------------------------------------------------------------------
+    fn poll_child( &mut self, index: usize )
+    {
+        let Some(Some((child, waker))) = self.children.get_mut(index) else
+        {
+            return;
+        };
+        let mut cx = Context::from_waker(waker);
+        if child.as_mut().poll(&mut cx).is_ready()
+        {
+            self.children[index] = None;
+            self.free.push(index);
+            self.live -= 1;
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Stores a new child in a free slot and returns its index.
+    //--------------------------------------------------------------------------
+    fn insert_child( &mut self, child: ChildFuture<'env> ) -> usize
+    {
+        let index = self.free.pop().unwrap_or(self.children.len());
+        let waker = Waker::from(Arc::new(ChildWaker
+        {
+            wake_state: self.inner.wake_state.clone(),
+            index,
+        }));
+        if index == self.children.len()
+        {
+            self.children.push(None);
+        }
+        self.children[index] = Some((child, waker));
+        self.live += 1;
+        index
+    }
+}
+
+impl<'env, R> Unpin for ScopeFuture<'env, R> {}
+
+impl<'env, R> Future for ScopeFuture<'env, R>
+{
+    type Output = R;
+
+    //--------------------------------------------------------------------------
+    //  Polls the body and the children that have been woken, and the new
+    //  children. Returns the output of the body when all of them have
+    //  completed.
+    //--------------------------------------------------------------------------
+    fn poll( self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<R>
+    {
+        let this = self.get_mut();
+        let wake_state = this.inner.wake_state.clone();
+        *wake_state.waker.lock().unwrap() = Some(cx.waker().clone());
+
+        //  A child woken while it is polled is polled again at the next poll
+        //  of the scope.
+        let mut woken = std::mem::take(&mut *wake_state.woken.lock().unwrap());
+        woken.sort_unstable();
+        woken.dedup();
+        for index in woken
+        {
+            match index
+            {
+                BODY => this.poll_body(),
+                _ => this.poll_child(index),
+            }
+        }
+
+        //  Polls each new child once. Children spawned while polling are
+        //  polled in the same call.
+        loop
+        {
+            let mut spawned_guard = this.inner.spawned.lock().unwrap();
+            let spawned = std::mem::take(&mut *spawned_guard);
+            drop(spawned_guard);
+            if spawned.is_empty()
+            {
+                break;
+            }
+            for child in spawned
+            {
+                let index = this.insert_child(child);
+                this.poll_child(index);
+            }
+        }
+
+        if this.body.is_none() && this.live == 0
+        {
+            //  No more children can be spawned without the body, except from
+            //  a leaked `Scope` .
+            if let Some(output) = this.output.take()
+            {
+                return Poll::Ready(output);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl<'env, R> Debug for ScopeFuture<'env, R>
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result
+    {
+        write!(f, "ScopeFuture{{children={}}}", self.live)
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Handle to await the output of a task spawned with `Scope::spawn` .
+//
+//  Awaiting it returns `JoinError::Cancelled` if the scope was dropped before
+//  the child completed.
+//------------------------------------------------------------------------------
+pub struct ScopedJoinHandle<T: Send>
+{
+    receiver: Receiver<T>,
+}
+
+impl<T: Send> Future for ScopedJoinHandle<T>
+{
+    type Output = Result<T, JoinError>;
+
+    fn poll
+    (
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Self::Output>
+    {
+        match Pin::new(&mut self.receiver).poll(cx)
+        {
+            Poll::Ready(Ok(value)) => Poll::Ready(Ok(value)),
+            Poll::Ready(Err(_)) => Poll::Ready(Err(JoinError::Cancelled)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T: Send> Debug for ScopedJoinHandle<T>
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result
+    {
+        write!(f, "ScopedJoinHandle<{}>", std::any::type_name::<T>())
+    }
+}
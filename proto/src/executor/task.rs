@@ -0,0 +1,439 @@
+/*
+
+    Spawned task.
+
+    A task is a single allocation, an `Arc<TaskCell<F>>` , that holds the
+    header, the future and, through the `TaskSlot` trait object, a vtable. The
+    waker of a task is a `RawWaker` pointing at the task itself, so creating,
+    cloning and waking it do not allocate. Scheduling a task pushes the `Arc`
+    itself to the ready queue of the executor.
+
+*/
+
+use crate::executor::{ coop, set_thread_executor, Executor, TaskFuture };
+use crate::executor::{ set_in_worker, TaskId };
+use crate::threadpool::Runnable;
+
+use core::mem::ManuallyDrop;
+use core::pin::Pin;
+use core::task::{ Context, Poll, RawWaker, RawWakerVTable, Waker };
+use std::panic::{ catch_unwind, AssertUnwindSafe };
+use std::sync::atomic::{ AtomicBool, AtomicU8, Ordering };
+use std::sync::{ Arc, Mutex, Weak };
+
+pub(crate) type SpawnedTask = Arc<dyn TaskSlot>;
+
+
+//------------------------------------------------------------------------------
+//  Scheduling states of a spawned task.
+//
+//  - `IDLE` : waiting for a wakeup. Not in the ready queue.
+//  - `SCHEDULED` : in the ready queue, waiting to be polled.
+//  - `RUNNING` : being polled.
+//  - `NOTIFIED` : woken while being polled. Scheduled again after the poll.
+//  - `COMPLETE` : completed, panicked or aborted. Never polled again.
+//
+//  Each wakeup moves an `IDLE` task to `SCHEDULED` or a `RUNNING` task to
+//  `NOTIFIED` , and does nothing in the other states, so a task is queued at
+//  most once.
+//
+//  The queue is the `ReadyQueue` of the executor, in which the threadpool
+//  pops tasks by priority, or the LIFO slot of the worker if the task is woken
+//  by the task running on it.
+//------------------------------------------------------------------------------
+const TASK_IDLE: u8 = 0;
+const TASK_SCHEDULED: u8 = 1;
+const TASK_RUNNING: u8 = 2;
+const TASK_NOTIFIED: u8 = 3;
+const TASK_COMPLETE: u8 = 4;
+
+
+//------------------------------------------------------------------------------
+//  Header of a spawned task. Holds the state that does not depend on the type
+//  of the future.
+//------------------------------------------------------------------------------
+pub(crate) struct TaskHeader
+{
+    id: TaskId,
+    priority: u8,
+    aborted: AtomicBool,
+    state: AtomicU8,
+    executor: Weak<Executor>,
+}
+
+impl TaskHeader
+{
+    //--------------------------------------------------------------------------
+    //  Returns the priority the task was spawned with. Every wakeup queues the
+    //  task with this priority.
+    //--------------------------------------------------------------------------
+    pub(crate) fn priority( &self ) -> u8
+    {
+        self.priority
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the executor of the task.
+    //--------------------------------------------------------------------------
+    pub(crate) fn executor( &self ) -> &Weak<Executor>
+    {
+        &self.executor
+    }
+
+    //--------------------------------------------------------------------------
+    //  Records a wakeup. Returns true if the task must be queued, that is, if
+    //  it was `IDLE` .
+    //--------------------------------------------------------------------------
+    pub(crate) fn transition_to_scheduled( &self ) -> bool
+    {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop
+        {
+            let next = match state
+            {
+                TASK_IDLE => TASK_SCHEDULED,
+                TASK_RUNNING => TASK_NOTIFIED,
+                _ => return false,
+            };
+            match self.state.compare_exchange_weak
+            (
+                state,
+                next,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            {
+                Ok(_) => return next == TASK_SCHEDULED,
+                Err(actual) => state = actual,
+            }
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Starts a poll. Returns false if the task is not `SCHEDULED` .
+    //--------------------------------------------------------------------------
+    fn transition_to_running( &self ) -> bool
+    {
+        self.state.compare_exchange
+        (
+            TASK_SCHEDULED,
+            TASK_RUNNING,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        )
+        .is_ok()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Ends a poll that returned `Poll::Pending` . Returns true if the task was
+    //  woken during the poll and must be queued again.
+    //--------------------------------------------------------------------------
+    fn transition_to_idle( &self ) -> bool
+    {
+        match self.state.compare_exchange
+        (
+            TASK_RUNNING,
+            TASK_IDLE,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        )
+        {
+            Ok(_) => false,
+            Err(_) =>
+            {
+                //  Only a wakeup changes the state during a poll.
+                self.state.store(TASK_SCHEDULED, Ordering::Release);
+                true
+            },
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Marks the task as complete. Later wakeups are ignored.
+    //--------------------------------------------------------------------------
+    pub(crate) fn transition_to_complete( &self )
+    {
+        self.state.store(TASK_COMPLETE, Ordering::Release);
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Type-erased spawned task.
+//------------------------------------------------------------------------------
+pub(crate) trait TaskSlot: Send + Sync
+{
+    //--------------------------------------------------------------------------
+    //  Returns the header of the task.
+    //--------------------------------------------------------------------------
+    fn header( &self ) -> &TaskHeader;
+
+    //--------------------------------------------------------------------------
+    //  Returns true if the future has completed or been dropped.
+    //--------------------------------------------------------------------------
+    fn is_finished( &self ) -> bool;
+
+    //--------------------------------------------------------------------------
+    //  Drops the future unless it is being polled by another thread.
+    //--------------------------------------------------------------------------
+    fn drop_future_if_idle( &self );
+
+    //--------------------------------------------------------------------------
+    //  Converts the task to a job of the threadpool.
+    //--------------------------------------------------------------------------
+    fn into_runnable( self: Arc<Self> ) -> Arc<dyn Runnable>;
+}
+
+impl dyn TaskSlot
+{
+    //--------------------------------------------------------------------------
+    //  Returns the identifier of the task.
+    //--------------------------------------------------------------------------
+    pub(crate) fn id( &self ) -> TaskId
+    {
+        self.header().id
+    }
+
+    //--------------------------------------------------------------------------
+    //  Marks the task as aborted. The future is dropped at the next time the
+    //  task is polled.
+    //--------------------------------------------------------------------------
+    pub(crate) fn abort( &self )
+    {
+        self.header().aborted.store(true, Ordering::Release);
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns true if the task was aborted.
+    //--------------------------------------------------------------------------
+    pub(crate) fn is_aborted( &self ) -> bool
+    {
+        self.header().aborted.load(Ordering::Acquire)
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Allocation of a spawned task.
+//
+//  `future` becomes `None` when the task completes or is aborted. The future
+//  is pinned in the allocation, so it is dropped in place and never moved out
+//  of `future` .
+//------------------------------------------------------------------------------
+pub(crate) struct TaskCell<F: TaskFuture>
+{
+    header: TaskHeader,
+    future: Mutex<Option<F>>,
+}
+
+impl<F: TaskFuture + 'static> TaskCell<F>
+{
+    const WAKER_VTABLE: RawWakerVTable = RawWakerVTable::new
+    (
+        Self::clone_waker,
+        Self::wake,
+        Self::wake_by_ref,
+        Self::drop_waker,
+    );
+
+    //--------------------------------------------------------------------------
+    //  Creates a new task that will execute `fut` with `priority` .
+    //--------------------------------------------------------------------------
+    pub(crate) fn new
+    (
+        id: TaskId,
+        priority: u8,
+        fut: F,
+        executor: Weak<Executor>,
+    ) -> Arc<Self>
+    {
+        Arc::new(Self
+        {
+            header: TaskHeader
+            {
+                id,
+                priority,
+                aborted: AtomicBool::new(false),
+                state: AtomicU8::new(TASK_IDLE),
+                executor,
+            },
+            future: Mutex::new(Some(fut)),
+        })
+    }
+
+    //--------------------------------------------------------------------------
+    //  Schedules the task on its executor, if it is still alive.
+    //--------------------------------------------------------------------------
+    fn schedule( self: Arc<Self> )
+    {
+        if let Some(executor) = self.header.executor.upgrade()
+        {
+            executor.schedule_task(self);
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Functions of `WAKER_VTABLE` . The data pointer of the `RawWaker` is a
+    //  pointer to the task created by `Arc::into_raw` or `Arc::as_ptr` .
+    //--------------------------------------------------------------------------
+    unsafe fn clone_waker( ptr: *const () ) -> RawWaker
+    {
+        Arc::increment_strong_count(ptr.cast::<Self>());
+        RawWaker::new(ptr, &Self::WAKER_VTABLE)
+    }
+
+    unsafe fn wake( ptr: *const () )
+    {
+        Arc::from_raw(ptr.cast::<Self>()).schedule();
+    }
+
+    unsafe fn wake_by_ref( ptr: *const () )
+    {
+        let task = ManuallyDrop::new(Arc::from_raw(ptr.cast::<Self>()));
+        Arc::clone(&task).schedule();
+    }
+
+    unsafe fn drop_waker( ptr: *const () )
+    {
+        drop(Arc::from_raw(ptr.cast::<Self>()));
+    }
+}
+
+impl<F: TaskFuture> Drop for TaskCell<F>
+{
+    //--------------------------------------------------------------------------
+    //  A pending task that nothing can wake anymore is dropped without being
+    //  polled again, so it stops being a live task here.
+    //--------------------------------------------------------------------------
+    fn drop( &mut self )
+    {
+        if let Some(executor) = self.header.executor.upgrade()
+        {
+            executor.remove_task(self.header.id);
+        }
+    }
+}
+
+impl<F: TaskFuture + 'static> TaskSlot for TaskCell<F>
+{
+    fn header( &self ) -> &TaskHeader
+    {
+        &self.header
+    }
+
+    fn is_finished( &self ) -> bool
+    {
+        match self.future.try_lock()
+        {
+            Ok(guard) => guard.is_none(),
+            Err(_) => false,
+        }
+    }
+
+    fn drop_future_if_idle( &self )
+    {
+        if let Ok(mut guard) = self.future.try_lock()
+        {
+            *guard = None;
+        }
+    }
+
+    fn into_runnable( self: Arc<Self> ) -> Arc<dyn Runnable>
+    {
+        self
+    }
+}
+
+impl<F: TaskFuture + 'static> Runnable for TaskCell<F>
+{
+    //--------------------------------------------------------------------------
+    //  Polls the task once on the current worker thread.
+    //--------------------------------------------------------------------------
+    fn run( self: Arc<Self> )
+    {
+        let Some(executor) = self.header.executor.upgrade() else { return };
+        if !self.header.transition_to_running()
+        {
+            return;
+        }
+
+        //  The waker borrows the reference held by `self` , so it must not be
+        //  dropped. Clones of it own a reference.
+        let raw_waker = RawWaker::new
+        (
+            Arc::as_ptr(&self).cast::<()>(),
+            &Self::WAKER_VTABLE,
+        );
+        let waker = ManuallyDrop::new(unsafe { Waker::from_raw(raw_waker) });
+        let mut cx = Context::from_waker(&waker);
+        let id = self.header.id;
+        let mut opt_fut_guard = self.future.lock().unwrap();
+
+        //  Dropping the future of an aborted task drops the sender of the
+        //  `JoinHandle` , which wakes the awaiting task with
+        //  `JoinError::Cancelled` .
+        if self.header.aborted.load(Ordering::Acquire)
+        {
+            self.header.transition_to_complete();
+            *opt_fut_guard = None;
+            drop(opt_fut_guard);
+            executor.remove_task(id);
+            return;
+        }
+
+        let Some(fut) = opt_fut_guard.as_mut() else
+        {
+            self.header.transition_to_complete();
+            return;
+        };
+
+        //  The future lives in the `Arc` allocation and is never moved.
+        let mut fut = unsafe { Pin::new_unchecked(fut) };
+
+        let _guard = set_thread_executor(self.header.executor.clone());
+        let _in_worker_guard = set_in_worker(Arc::as_ptr(&executor));
+        if let Some(before_poll) = &executor.before_poll
+        {
+            before_poll(id);
+        }
+        let result = catch_unwind(AssertUnwindSafe(||
+        {
+            coop::budget(|| fut.as_mut().poll(&mut cx))
+        }));
+        if let Some(after_poll) = &executor.after_poll
+        {
+            after_poll(id);
+        }
+
+        match result
+        {
+            Ok(Poll::Ready(())) =>
+            {
+                self.header.transition_to_complete();
+                *opt_fut_guard = None;
+                drop(opt_fut_guard);
+                executor.remove_task(id);
+            },
+            Ok(Poll::Pending) =>
+            {
+                drop(opt_fut_guard);
+                if self.header.transition_to_idle()
+                {
+                    executor.submit_task(self);
+                }
+            },
+
+            //  Drops the panicked future and reports the payload to the panic
+            //  handler and then to the `JoinHandle` .
+            Err(payload) =>
+            {
+                self.header.transition_to_complete();
+                executor.handle_panic(payload.as_ref());
+                fut.panicked(payload);
+                *opt_fut_guard = None;
+                drop(opt_fut_guard);
+                executor.remove_task(id);
+            }
+        }
+    }
+}
@@ -0,0 +1,455 @@
+/*
+
+    Task-local storage.
+
+    Values are stored in the future and are moved into a thread-local slot
+    only while the future is polled, so they follow the task when it is polled
+    on a different worker thread.
+
+
+    ```rust
+    wexing::task_local!
+    {
+        static REQUEST_ID: u64;
+    }
+
+    async fn handle_request()
+    {
+        let id = REQUEST_ID.get();
+        println!("handling request {}", id);
+    }
+
+    let executor = wexing::executor::Executor::default();
+    executor.block_on(REQUEST_ID.scope(42, handle_request()));
+    ```
+
+    Values set with `scope_inherited` are also set in the tasks spawned by the
+    scoped future with `executor::spawn` .
+
+*/
+
+use crate::executor::error::AccessError;
+
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{ Context, Poll };
+use std::fmt::{ Debug, Formatter };
+use std::thread::LocalKey;
+
+
+//------------------------------------------------------------------------------
+//  Declares task-local keys of type `TaskLocalKey` .
+//------------------------------------------------------------------------------
+#[macro_export]
+macro_rules! task_local
+{
+    () => {};
+
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty; $($rest:tt)*) =>
+    {
+        $crate::__task_local_inner!($(#[$attr])* $vis $name, $t);
+        $crate::task_local!($($rest)*);
+    };
+
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty) =>
+    {
+        $crate::__task_local_inner!($(#[$attr])* $vis $name, $t);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __task_local_inner
+{
+    ($(#[$attr:meta])* $vis:vis $name:ident, $t:ty) =>
+    {
+        $(#[$attr])*
+        $vis static $name: $crate::executor::TaskLocalKey<$t> =
+        {
+            std::thread_local!
+            {
+                static __KEY: std::cell::RefCell<Option<$t>> =
+                    const { std::cell::RefCell::new(None) };
+            }
+            $crate::executor::TaskLocalKey { inner: __KEY }
+        };
+    };
+}
+
+
+//------------------------------------------------------------------------------
+//  Keys of the task-local values that are currently set on this thread and are
+//  inherited by spawned tasks.
+//------------------------------------------------------------------------------
+thread_local!
+{
+    static INHERITABLE: RefCell<Vec<&'static dyn Inheritable>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+
+//------------------------------------------------------------------------------
+//  A key for task-local data. Declare it with `task_local!` .
+//------------------------------------------------------------------------------
+pub struct TaskLocalKey<T: 'static>
+{
+    #[doc(hidden)]
+    pub inner: LocalKey<RefCell<Option<T>>>,
+}
+
+impl<T: 'static> TaskLocalKey<T>
+{
+    //--------------------------------------------------------------------------
+    //  Sets the value of this key to `value` while `fut` is polled.
+    //--------------------------------------------------------------------------
+    pub fn scope<F: Future>( &'static self, value: T, fut: F )
+        -> TaskLocalFuture<T, F>
+    {
+        TaskLocalFuture
+        {
+            key: self,
+            slot: Some(value),
+            future: Box::pin(fut),
+            inherit: None,
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the value of this key to `value` while `f` runs.
+    //--------------------------------------------------------------------------
+    pub fn sync_scope<R>( &'static self, value: T, f: impl FnOnce() -> R )
+        -> R
+    {
+        let mut slot = Some(value);
+        let _guard = SwapGuard::new(self, &mut slot);
+        f()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Calls `f` with a reference to the value of this key.
+    //
+    //  Panics if the value is not set for the current task.
+    //--------------------------------------------------------------------------
+    pub fn with<R>( &'static self, f: impl FnOnce(&T) -> R ) -> R
+    {
+        match self.try_with(f)
+        {
+            Ok(result) => result,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Calls `f` with a reference to the value of this key, or returns
+    //  `AccessError` if the value is not set for the current task.
+    //--------------------------------------------------------------------------
+    pub fn try_with<R>( &'static self, f: impl FnOnce(&T) -> R )
+        -> Result<R, AccessError>
+    {
+        self.inner.with(|cell|
+        {
+            match cell.borrow().as_ref()
+            {
+                Some(value) => Ok(f(value)),
+                None => Err(AccessError),
+            }
+        })
+    }
+
+    //--------------------------------------------------------------------------
+    //  Swaps the value of this key on the current thread with `slot` .
+    //--------------------------------------------------------------------------
+    fn swap( &'static self, slot: &mut Option<T> )
+    {
+        self.inner.with(|cell|
+        {
+            core::mem::swap(&mut *cell.borrow_mut(), slot);
+        });
+    }
+}
+
+impl<T: Clone + 'static> TaskLocalKey<T>
+{
+    //--------------------------------------------------------------------------
+    //  Returns a copy of the value of this key.
+    //
+    //  Panics if the value is not set for the current task.
+    //--------------------------------------------------------------------------
+    pub fn get( &'static self ) -> T
+    {
+        self.with(Clone::clone)
+    }
+}
+
+impl<T: Clone + Send + 'static> TaskLocalKey<T>
+{
+    //--------------------------------------------------------------------------
+    //  Sets the value of this key to `value` while `fut` is polled. Tasks
+    //  spawned by `fut` with `executor::spawn` start with a clone of the value.
+    //--------------------------------------------------------------------------
+    pub fn scope_inherited<F: Future>( &'static self, value: T, fut: F )
+        -> TaskLocalFuture<T, F>
+    {
+        TaskLocalFuture
+        {
+            key: self,
+            slot: Some(value),
+            future: Box::pin(fut),
+            inherit: Some(self),
+        }
+    }
+}
+
+impl<T: 'static> Debug for TaskLocalKey<T>
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result
+    {
+        write!(f, "TaskLocalKey<{}>", std::any::type_name::<T>())
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Swaps a task-local value into the thread-local slot and swaps it back when
+//  dropped, even if the future panics.
+//------------------------------------------------------------------------------
+struct SwapGuard<'a, T: 'static>
+{
+    key: &'static TaskLocalKey<T>,
+    slot: &'a mut Option<T>,
+}
+
+impl<'a, T: 'static> SwapGuard<'a, T>
+{
+    fn new( key: &'static TaskLocalKey<T>, slot: &'a mut Option<T> ) -> Self
+    {
+        key.swap(slot);
+        Self { key, slot }
+    }
+}
+
+impl<'a, T: 'static> Drop for SwapGuard<'a, T>
+{
+    fn drop( &mut self )
+    {
+        self.key.swap(self.slot);
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Pushes keys to the inheritable keys of this thread and pops them when
+//  dropped.
+//------------------------------------------------------------------------------
+struct InheritGuard
+{
+    len: usize,
+}
+
+impl InheritGuard
+{
+    fn new( keys: impl Iterator<Item = &'static dyn Inheritable> ) -> Self
+    {
+        INHERITABLE.with(|cell|
+        {
+            let mut stack = cell.borrow_mut();
+            let len = stack.len();
+            stack.extend(keys);
+            Self { len }
+        })
+    }
+}
+
+impl Drop for InheritGuard
+{
+    fn drop( &mut self )
+    {
+        INHERITABLE.with(|cell| cell.borrow_mut().truncate(self.len));
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Future that sets a task-local value while the inner future is polled.
+//------------------------------------------------------------------------------
+pub struct TaskLocalFuture<T: 'static, F: Future>
+{
+    key: &'static TaskLocalKey<T>,
+    slot: Option<T>,
+    future: Pin<Box<F>>,
+    inherit: Option<&'static dyn Inheritable>,
+}
+
+//  The value is never pinned; it is moved in and out of the thread-local slot.
+impl<T: 'static, F: Future> Unpin for TaskLocalFuture<T, F> {}
+
+impl<T: 'static, F: Future> Future for TaskLocalFuture<T, F>
+{
+    type Output = F::Output;
+
+    fn poll( self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<F::Output>
+    {
+        let this = self.get_mut();
+        let _inherit_guard = this.inherit
+            .map(|key| InheritGuard::new(core::iter::once(key)));
+        let _swap_guard = SwapGuard::new(this.key, &mut this.slot);
+        this.future.as_mut().poll(cx)
+    }
+}
+
+impl<T: 'static, F: Future> Debug for TaskLocalFuture<T, F>
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result
+    {
+        write!(f, "TaskLocalFuture<{}>", std::any::type_name::<T>())
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  A task-local key whose value can be copied into a spawned task.
+//------------------------------------------------------------------------------
+pub(crate) trait Inheritable: Sync
+{
+    //--------------------------------------------------------------------------
+    //  Returns a copy of the current value of the key.
+    //--------------------------------------------------------------------------
+    fn snapshot( &'static self ) -> Option<Box<dyn InheritedValue>>;
+}
+
+impl<T: Clone + Send + 'static> Inheritable for TaskLocalKey<T>
+{
+    fn snapshot( &'static self ) -> Option<Box<dyn InheritedValue>>
+    {
+        self.try_with(Clone::clone).ok().map(|value|
+        {
+            Box::new(InheritedSlot { key: self, slot: Some(value) })
+                as Box<dyn InheritedValue>
+        })
+    }
+}
+
+
+
+//------------------------------------------------------------------------------
+//  A copy of a task-local value owned by a spawned task.
+//------------------------------------------------------------------------------
+pub(crate) trait InheritedValue: Send
+{
+    fn swap( &mut self );
+    fn key( &self ) -> &'static dyn Inheritable;
+}
+
+struct InheritedSlot<T: Clone + Send + 'static>
+{
+    key: &'static TaskLocalKey<T>,
+    slot: Option<T>,
+}
+
+impl<T: Clone + Send + 'static> InheritedValue for InheritedSlot<T>
+{
+    fn swap( &mut self )
+    {
+        self.key.swap(&mut self.slot);
+    }
+
+    fn key( &self ) -> &'static dyn Inheritable
+    {
+        self.key
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Copies the inheritable task-local values set on the current thread.
+//------------------------------------------------------------------------------
+pub(crate) fn snapshot() -> Vec<Box<dyn InheritedValue>>
+{
+    let keys = INHERITABLE.with(|cell| cell.borrow().clone());
+    let mut values: Vec<Box<dyn InheritedValue>> = Vec::new();
+    for key in keys
+    {
+        //  A key may be pushed more than once by nested scopes.
+        let seen = values.iter().any(|value|
+        {
+            core::ptr::addr_eq(value.key(), key)
+        });
+        if !seen
+        {
+            values.extend(key.snapshot());
+        }
+    }
+    values
+}
+
+
+//------------------------------------------------------------------------------
+//  Future of a spawned task that sets the task-local values inherited from
+//  the spawning task while the inner future is polled.
+//------------------------------------------------------------------------------
+pub(crate) struct InheritFuture<F: Future>
+{
+    future: F,
+    values: Vec<Box<dyn InheritedValue>>,
+}
+
+impl<F: Future> InheritFuture<F>
+{
+    pub(crate) fn new( future: F, values: Vec<Box<dyn InheritedValue>> )
+        -> Self
+    {
+        Self { future, values }
+    }
+}
+
+impl<F: Future> Future for InheritFuture<F>
+{
+    type Output = F::Output;
+
+    fn poll( self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<F::Output>
+    {
+        //  `future` is pinned with `self` and never moved; `values` is not
+        //  pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if this.values.is_empty()
+        {
+            return future.poll(cx);
+        }
+
+        let _inherit_guard = InheritGuard::new
+        (
+            this.values.iter().map(|value| value.key())
+        );
+        let _swap_guard = InheritSwapGuard::new(&mut this.values);
+        future.poll(cx)
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Swaps inherited values into the thread-local slots and swaps them back in
+//  reverse order when dropped.
+//------------------------------------------------------------------------------
+struct InheritSwapGuard<'a>
+{
+    values: &'a mut Vec<Box<dyn InheritedValue>>,
+}
+
+impl<'a> InheritSwapGuard<'a>
+{
+    fn new( values: &'a mut Vec<Box<dyn InheritedValue>> ) -> Self
+    {
+        values.iter_mut().for_each(|value| value.swap());
+        Self { values }
+    }
+}
+
+impl<'a> Drop for InheritSwapGuard<'a>
+{
+    fn drop( &mut self )
+    {
+        self.values.iter_mut().rev().for_each(|value| value.swap());
+    }
+}
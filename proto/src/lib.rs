@@ -5,6 +5,7 @@ pub(crate) mod util;
 pub mod timer;
 pub mod sync;
 pub mod select;
+pub mod stream;
 pub mod net;
 
 pub mod threadpool;
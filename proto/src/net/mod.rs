@@ -10,6 +10,8 @@ pub use tcp_stream::*;
 mod tcp_listener;
 pub use tcp_listener::*;
 
+use crate::executor::coop::{ consume_budget, restore_budget };
+
 use core::time::Duration;
 
 async fn sleep()
@@ -4,7 +4,7 @@
 
 */
 
-use super::{ sleep, TcpStream };
+use super::{ consume_budget, restore_budget, sleep, TcpStream };
 use std::io::ErrorKind;
 use std::net::{ SocketAddr, ToSocketAddrs };
 
@@ -84,6 +84,7 @@ impl TcpListener
     {
         loop
         {
+            consume_budget().await;
             match self.std_listener.accept()
             {
                 Ok((std_stream, addr)) =>
@@ -92,6 +93,7 @@ impl TcpListener
                 },
                 Err(e) if e.kind() == ErrorKind::WouldBlock =>
                 {
+                    restore_budget();
                     sleep().await;
                 },
                 Err(e) => return Err(e),
@@ -7,7 +7,7 @@
 use std::io::{ ErrorKind, Read, Write };
 use std::net::ToSocketAddrs;
 
-use super::sleep;
+use super::{ consume_budget, restore_budget, sleep };
 
 
 //------------------------------------------------------------------------------
@@ -86,11 +86,16 @@ impl TcpStream
     {
         loop
         {
+            consume_budget().await;
             match self.std_stream.read(buf)
             {
                 Ok(num_read) => return Ok(num_read),
                 Err(e) if e.kind() == ErrorKind::WouldBlock
-                || e.kind() == ErrorKind::TimedOut => { sleep().await; },
+                || e.kind() == ErrorKind::TimedOut =>
+                {
+                    restore_budget();
+                    sleep().await;
+                },
                 Err(e) => return Err(e),
             }
         }
@@ -110,6 +115,7 @@ impl TcpStream
         let mut total_read: usize = 0;
         loop
         {
+            consume_budget().await;
             match self.std_stream.read(&mut chunk)
             {
                 Ok(0) => return Ok(total_read),
@@ -119,8 +125,15 @@ impl TcpStream
                     total_read += num_read;
                 },
                 Err(e) if e.kind() == ErrorKind::WouldBlock
-                || e.kind() == ErrorKind::TimedOut => { sleep().await; },
-                Err(e) if e.kind() == ErrorKind::Interrupted => {},
+                || e.kind() == ErrorKind::TimedOut =>
+                {
+                    restore_budget();
+                    sleep().await;
+                },
+                Err(e) if e.kind() == ErrorKind::Interrupted =>
+                {
+                    restore_budget();
+                },
                 Err(e) => return Err(e),
             }
         }
@@ -160,6 +173,7 @@ impl TcpStream
         let mut dest = buf;
         while !dest.is_empty()
         {
+            consume_budget().await;
             match self.std_stream.read(dest)
             {
                 Ok(0) =>
@@ -171,8 +185,15 @@ impl TcpStream
                 },
                 Ok(num_read) => { dest = &mut dest[num_read..]; },
                 Err(e) if e.kind() == ErrorKind::WouldBlock
-                || e.kind() == ErrorKind::TimedOut => { sleep().await },
-                Err(e) if e.kind() == ErrorKind::Interrupted => {},
+                || e.kind() == ErrorKind::TimedOut =>
+                {
+                    restore_budget();
+                    sleep().await;
+                },
+                Err(e) if e.kind() == ErrorKind::Interrupted =>
+                {
+                    restore_budget();
+                },
                 Err(e) => return Err(e),
             }
         }
@@ -193,11 +214,16 @@ impl TcpStream
     {
         loop
         {
+            consume_budget().await;
             match self.std_stream.read_vectored(bufs)
             {
                 Ok(num_read) => return Ok(num_read),
                 Err(e) if e.kind() == ErrorKind::WouldBlock
-                || e.kind() == ErrorKind::TimedOut => { sleep().await; },
+                || e.kind() == ErrorKind::TimedOut =>
+                {
+                    restore_budget();
+                    sleep().await;
+                },
                 Err(e) => return Err(e),
             }
         }
@@ -216,11 +242,16 @@ impl TcpStream
     {
         loop
         {
+            consume_budget().await;
             match self.std_stream.peek(buf)
             {
                 Ok(num_read) => return Ok(num_read),
                 Err(e) if e.kind() == ErrorKind::WouldBlock
-                || e.kind() == ErrorKind::TimedOut => { sleep().await; },
+                || e.kind() == ErrorKind::TimedOut =>
+                {
+                    restore_budget();
+                    sleep().await;
+                },
                 Err(e) => return Err(e),
             }
         }
@@ -235,6 +266,7 @@ impl TcpStream
     {
         loop
         {
+            consume_budget().await;
             match self.std_stream.write(buf)
             {
                 Ok(num) => return Ok(num),
@@ -243,7 +275,11 @@ impl TcpStream
                 ||
                 (
                     e.kind() == ErrorKind::Other && e.raw_os_error() == Some(41)
-                ) => { sleep().await; },
+                ) =>
+                {
+                    restore_budget();
+                    sleep().await;
+                },
                 Err(e) => return Err(e),
             }
         }
@@ -257,6 +293,7 @@ impl TcpStream
     {
         loop
         {
+            consume_budget().await;
             match self.std_stream.flush()
             {
                 Ok(()) => return Ok(()),
@@ -265,7 +302,11 @@ impl TcpStream
                 ||
                 (
                     e.kind() == ErrorKind::Other && e.raw_os_error() == Some(41)
-                ) => { sleep().await; },
+                ) =>
+                {
+                    restore_budget();
+                    sleep().await;
+                },
                 Err(e) => return Err(e),
             }
         }
@@ -282,6 +323,7 @@ impl TcpStream
     {
         while !buf.is_empty()
         {
+            consume_budget().await;
             match self.std_stream.write(buf)
             {
                 Ok(0) => {},
@@ -291,7 +333,11 @@ impl TcpStream
                 ||
                 (
                     e.kind() == ErrorKind::Other && e.raw_os_error() == Some(41)
-                ) => { sleep().await; },
+                ) =>
+                {
+                    restore_budget();
+                    sleep().await;
+                },
                 Err(e) => return Err(e),
             }
         }
@@ -314,6 +360,7 @@ impl TcpStream
     {
         loop
         {
+            consume_budget().await;
             match self.std_stream.write_vectored(bufs)
             {
                 Ok(num) => return Ok(num),
@@ -322,7 +369,11 @@ impl TcpStream
                 ||
                 (
                     e.kind() == ErrorKind::Other && e.raw_os_error() == Some(41)
-                ) => { sleep().await; },
+                ) =>
+                {
+                    restore_budget();
+                    sleep().await;
+                },
                 Err(e) => return Err(e),
             }
         }
@@ -0,0 +1,77 @@
+/*
+
+    Asynchronous sequence of values.
+
+    ```rust
+    use wexing::stream::Stream;
+
+    async fn sum<S: Stream<Item = u32> + Unpin>( mut stream: S ) -> u32
+    {
+        let mut total = 0;
+        while let Some(value) = stream.next().await
+        {
+            total += value;
+        }
+        total
+    }
+    ```
+
+*/
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{ Context, Poll };
+
+
+//------------------------------------------------------------------------------
+//  Asynchronous counterpart of `Iterator` . Has the same signature as the
+//  `Stream` trait of the `futures` crate.
+//------------------------------------------------------------------------------
+pub trait Stream
+{
+    type Item;
+
+    //--------------------------------------------------------------------------
+    //  Returns `Poll::Ready(Some(item))` for the next item, `Poll::Ready(None)`
+    //  when the stream is finished, and `Poll::Pending` if the next item is not
+    //  ready yet.
+    //--------------------------------------------------------------------------
+    fn poll_next
+    (
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<Option<Self::Item>>;
+
+    //--------------------------------------------------------------------------
+    //  Returns a future that resolves to the next item.
+    //--------------------------------------------------------------------------
+    fn next( &mut self ) -> Next<'_, Self>
+    where
+        Self: Unpin
+    {
+        Next { stream: self }
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Future returned by `Stream::next` .
+//------------------------------------------------------------------------------
+pub struct Next<'a, S: ?Sized>
+{
+    stream: &'a mut S,
+}
+
+impl<S: Stream + Unpin + ?Sized> Future for Next<'_, S>
+{
+    type Output = Option<S::Item>;
+
+    fn poll
+    (
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<Self::Output>
+    {
+        Pin::new(&mut *self.stream).poll_next(cx)
+    }
+}
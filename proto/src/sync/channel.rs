@@ -4,6 +4,8 @@
 
 */
 
+use crate::executor::coop;
+
 use core::future::Future;
 use core::pin::Pin;
 use core::task::{ Context, Poll };
@@ -276,9 +278,14 @@ impl<T: Send> Future for SendFuture<T>
     //--------------------------------------------------------------------------
     fn poll( self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<Self::Output>
     {
+        let Poll::Ready(coop) = coop::poll_proceed(cx) else
+        {
+            return Poll::Pending;
+        };
+
         let value = self.value.take().unwrap();
         let mut inner_guard = self.inner.lock().unwrap();
-        match self.sender.try_send(value)
+        let poll = match self.sender.try_send(value)
         {
             Ok(()) => Poll::Ready(Ok(())),
             Err(TrySendError::Disconnected(value)) =>
@@ -291,7 +298,12 @@ impl<T: Send> Future for SendFuture<T>
                 inner_guard.sender_wakers.push(cx.waker().clone());
                 Poll::Pending
             },
+        };
+        if poll.is_ready()
+        {
+            coop.made_progress();
         }
+        poll
     }
 }
 
@@ -468,8 +480,13 @@ impl<T: Send> Future for Receiver<T>
     //--------------------------------------------------------------------------
     fn poll( self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output>
     {
+        let Poll::Ready(coop) = coop::poll_proceed(cx) else
+        {
+            return Poll::Pending;
+        };
+
         let mut inner_guard = self.inner.lock().unwrap();
-        match self.receiver.as_ref().unwrap().try_recv()
+        let poll = match self.receiver.as_ref().unwrap().try_recv()
         {
             Ok(value) =>
             {
@@ -494,7 +511,12 @@ impl<T: Send> Future for Receiver<T>
                     Poll::Pending
                 }
             },
+        };
+        if poll.is_ready()
+        {
+            coop.made_progress();
         }
+        poll
     }
 }
 
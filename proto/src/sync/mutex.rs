@@ -4,6 +4,8 @@
 
 */
 
+use crate::executor::coop;
+
 use core::future::Future;
 use core::ops::{ Deref, DerefMut };
 use core::pin::Pin;
@@ -164,12 +166,18 @@ impl<'a, T> Future for LockFuture<'a, T>
     //--------------------------------------------------------------------------
     fn poll( self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<Self::Output>
     {
+        let Poll::Ready(coop) = coop::poll_proceed(cx) else
+        {
+            return Poll::Pending;
+        };
+
         loop
         {
             match self.mutex.value.try_lock()
             {
                 Ok(guard) =>
                 {
+                    coop.made_progress();
                     return Poll::Ready(MutexGuard::new(self.mutex, guard));
                 },
                 Err(TryLockError::Poisoned(e)) => panic!("{}", e),
@@ -0,0 +1,320 @@
+/*
+
+    Builder to configure a ThreadPool.
+
+
+    ```rust
+    let pool = wexing::threadpool::ThreadPool::builder()
+        .thread_name_fn(|num| format!("io-worker-{}", num))
+        .size(8)
+        .max_size(64)
+        .keep_alive(std::time::Duration::from_secs(30))
+        .queue_capacity(1024)
+        .stack_size(256 * 1024)
+        .on_thread_start(|| println!("started"))
+        .on_thread_stop(|| println!("stopped"))
+        .build()
+        .unwrap();
+    ```
+
+*/
+
+use crate::threadpool::{ Inner, ThreadPool };
+use crate::threadpool::error::NewThreadPoolError;
+use crate::util::AtomicCounter;
+
+use core::fmt::{ Debug, Formatter };
+use core::time::Duration;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{ Arc, Condvar, Mutex };
+
+//------------------------------------------------------------------------------
+//  Time a thread above `size` stays idle before it stops.
+//------------------------------------------------------------------------------
+const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+//------------------------------------------------------------------------------
+//  Number of queued jobs per thread when `queue_capacity` is not set.
+//------------------------------------------------------------------------------
+const DEFAULT_QUEUE_CAPACITY_PER_THREAD: usize = 200;
+
+//------------------------------------------------------------------------------
+//  Function that returns the name of a new worker thread. It receives the
+//  sequence number of the thread.
+//------------------------------------------------------------------------------
+pub type ThreadNameFn = dyn Fn(usize) -> String + Send + Sync + 'static;
+
+//------------------------------------------------------------------------------
+//  Function called on a worker thread when it starts or stops.
+//------------------------------------------------------------------------------
+pub type ThreadHook = dyn Fn() + Send + Sync + 'static;
+
+
+//------------------------------------------------------------------------------
+//  Builder for `ThreadPool` .
+//------------------------------------------------------------------------------
+#[derive(Clone)]
+pub struct ThreadPoolBuilder
+{
+    name: String,
+    thread_name: Option<Arc<ThreadNameFn>>,
+    size: usize,
+    max_size: Option<usize>,
+    keep_alive: Duration,
+    queue_capacity: Option<usize>,
+    stack_size: Option<usize>,
+    on_thread_start: Option<Arc<ThreadHook>>,
+    on_thread_stop: Option<Arc<ThreadHook>>,
+}
+
+impl ThreadPoolBuilder
+{
+    //--------------------------------------------------------------------------
+    //  Creates a new builder for a threadpool named "wexing" containing 4
+    //  threads.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn new() -> Self
+    {
+        Self
+        {
+            name: "wexing".to_string(),
+            thread_name: None,
+            size: 4,
+            max_size: None,
+            keep_alive: DEFAULT_KEEP_ALIVE,
+            queue_capacity: None,
+            stack_size: None,
+            on_thread_start: None,
+            on_thread_stop: None,
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the name of the threadpool. Threads are named with `name` with a
+    //  number unless `thread_name_fn` is set.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn name( mut self, name: impl Into<String> ) -> Self
+    {
+        self.name = name.into();
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the function that generates the names of the worker threads.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn thread_name_fn
+    (
+        mut self,
+        f: impl Fn(usize) -> String + Send + Sync + 'static,
+    ) -> Self
+    {
+        self.thread_name = Some(Arc::new(f));
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the number of threads in the pool.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn size( mut self, size: usize ) -> Self
+    {
+        self.size = size;
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the maximum number of threads. The pool starts more threads than
+    //  `size` when jobs are added while all threads are busy. Defaults to
+    //  `size` .
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn max_size( mut self, max_size: usize ) -> Self
+    {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets how long a thread above `size` stays idle before it stops.
+    //  Defaults to 10 seconds.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn keep_alive( mut self, keep_alive: Duration ) -> Self
+    {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the maximum number of queued jobs. When the queue is full,
+    //  `schedule` waits and `try_schedule` returns
+    //  `TryScheduleError::QueueFull` . Defaults to 200 jobs per thread.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn queue_capacity( mut self, queue_capacity: usize ) -> Self
+    {
+        self.queue_capacity = Some(queue_capacity);
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the stack size in bytes of the worker threads. Uses the default
+    //  of `std::thread` if not set.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn stack_size( mut self, stack_size: usize ) -> Self
+    {
+        self.stack_size = Some(stack_size);
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the function called on each worker thread when it starts, before
+    //  it executes any job.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn on_thread_start( mut self, f: impl Fn() + Send + Sync + 'static )
+        -> Self
+    {
+        self.on_thread_start = Some(Arc::new(f));
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the function called on each worker thread when it stops. It is
+    //  also called when the thread stops because a job panicked.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn on_thread_stop( mut self, f: impl Fn() + Send + Sync + 'static )
+        -> Self
+    {
+        self.on_thread_stop = Some(Arc::new(f));
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Creates the threadpool. The threads all start immediately.
+    //
+    //  After the `ThreadPool` struct drops, the threads continue processing
+    //  jobs and stop when the queue is empty.
+    //--------------------------------------------------------------------------
+    pub fn build( self ) -> Result<ThreadPool, NewThreadPoolError>
+    {
+        if self.name.is_empty() && self.thread_name.is_none()
+        {
+            return Err(NewThreadPoolError::Parameter
+            (
+                "ThreadPool::new called with empty name".to_string(),
+            ));
+        }
+
+        if self.size < 1
+        {
+            return Err(NewThreadPoolError::Parameter(format!
+            (
+                "ThreadPool::new called with invalid size value: {:?}",
+                self.size
+            )));
+        }
+
+        let max_size = self.max_size.unwrap_or(self.size);
+        if max_size < self.size
+        {
+            return Err(NewThreadPoolError::Parameter(format!
+            (
+                "ThreadPool::new called with max size {:?} less than size {:?}",
+                max_size,
+                self.size
+            )));
+        }
+
+        if self.keep_alive.is_zero()
+        {
+            return Err(NewThreadPoolError::Parameter
+            (
+                "ThreadPool::new called with invalid keep alive value: 0"
+                    .to_string(),
+            ));
+        }
+
+        if self.queue_capacity == Some(0)
+        {
+            return Err(NewThreadPoolError::Parameter
+            (
+                "ThreadPool::new called with invalid queue capacity value: 0"
+                    .to_string(),
+            ));
+        }
+
+        if self.stack_size == Some(0)
+        {
+            return Err(NewThreadPoolError::Parameter
+            (
+                "ThreadPool::new called with invalid stack size value: 0"
+                    .to_string(),
+            ));
+        }
+
+        //  Use a channel with bounded size.
+        //  If the channel was unbounded, the process could OOM (Out-Of-Memory)
+        //  when throughput goes down.
+        let queue_capacity = self
+            .queue_capacity
+            .unwrap_or(self.size * DEFAULT_QUEUE_CAPACITY_PER_THREAD);
+        let (sender, receiver) = std::sync::mpsc::sync_channel(queue_capacity);
+        let pool = ThreadPool
+        {
+            inner: Arc::new(Inner
+            {
+                name: self.name,
+                thread_name: self.thread_name,
+                next_name_num: AtomicCounter::new(),
+                size: self.size,
+                max_size,
+                keep_alive: self.keep_alive,
+                stack_size: self.stack_size,
+                on_thread_start: self.on_thread_start,
+                on_thread_stop: self.on_thread_stop,
+                receiver: Mutex::new(receiver),
+                workers: AtomicUsize::new(0),
+                idle_workers: AtomicUsize::new(0),
+                queued_jobs: AtomicUsize::new(0),
+                waiting_senders: Mutex::new(0),
+                stopping_helpers: Mutex::new(Vec::new()),
+                room: Condvar::new(),
+            }),
+            sender,
+        };
+
+        pool.inner.start_threads()?;
+        Ok(pool)
+    }
+}
+
+impl Default for ThreadPoolBuilder
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl Debug for ThreadPoolBuilder
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), core::fmt::Error>
+    {
+        write!
+        (
+            f,
+            "ThreadPoolBuilder{{{:?}, size={:?}, max_size={:?}, \
+            stack_size={:?}}}",
+            self.name,
+            self.size,
+            self.max_size,
+            self.stack_size
+        )
+    }
+}
@@ -31,14 +31,17 @@
 */
 
 pub mod error;
+mod builder;
+pub use builder::*;
 
 use crate::util::{ sleep_ms, AtomicCounter };
 use error::*;
 
 use core::fmt::{ Debug, Formatter };
 use core::time::Duration;
+use std::sync::atomic::{ AtomicU8, AtomicUsize, Ordering };
 use std::sync::mpsc::{ Receiver, RecvTimeoutError, SyncSender, TrySendError };
-use std::sync::{ Arc, Mutex };
+use std::sync::{ Arc, Condvar, Mutex, Weak };
 use std::time::Instant;
 
 
@@ -47,10 +50,27 @@ use std::time::Instant;
 //------------------------------------------------------------------------------
 struct Inner
 {
-    name: &'static str,
+    name: String,
+    thread_name: Option<Arc<ThreadNameFn>>,
     next_name_num: AtomicCounter,
     size: usize,
-    receiver: Mutex<Receiver<Box<dyn FnOnce() + Send>>>,
+    max_size: usize,
+    keep_alive: Duration,
+    stack_size: Option<usize>,
+    on_thread_start: Option<Arc<ThreadHook>>,
+    on_thread_stop: Option<Arc<ThreadHook>>,
+    receiver: Mutex<Receiver<Job>>,
+    workers: AtomicUsize,
+    idle_workers: AtomicUsize,
+    queued_jobs: AtomicUsize,
+
+    //  Number of `send` calls waiting for room in the full queue, and the
+    //  condition they wait on.
+    waiting_senders: Mutex<usize>,
+    room: Condvar,
+
+    //  Helper threads asked to stop that may still be alive, to reuse.
+    stopping_helpers: Mutex<Vec<Arc<HelperState>>>,
 }
 
 impl Inner
@@ -60,31 +80,11 @@ impl Inner
     //--------------------------------------------------------------------------
     fn start_threads( self: &Arc<Self> ) -> Result<(), StartThreadsError>
     {
-        while self.num_live_threads() < self.size
+        while let Some(workers) = self.reserve_worker(self.size)
         {
-            self.start_thread()?;
-        }
-        Ok(())
-    }
-
-    //--------------------------------------------------------------------------
-    //  Start a thread.
-    //--------------------------------------------------------------------------
-    fn start_thread( self: &Arc<Self> ) -> Result<(), StartThreadsError>
-    {
-        let self_clone = self.clone();
-        let num_live_threads = self.num_live_threads() - 1;
-
-        if num_live_threads < self.size
-        {
-            self.spawn_thread
-            (
-                format!("{}-{}", self.name, self.next_name_num.next()),
-                move || self_clone.work(),
-            )
-            .map_err(|e|
+            self.start_worker().map_err(|e|
             {
-                if num_live_threads == 0
+                if workers == 0
                 {
                     StartThreadsError::NoThreads(e)
                 }
@@ -94,10 +94,86 @@ impl Inner
                 }
             })?;
         }
-
         Ok(())
     }
 
+    //--------------------------------------------------------------------------
+    //  Starts one more thread if there are more queued jobs than idle threads
+    //  and the pool has less than `max_size` threads.
+    //--------------------------------------------------------------------------
+    fn grow( self: &Arc<Self> )
+    {
+        let queued_jobs = self.queued_jobs.load(Ordering::Acquire);
+        if queued_jobs <= self.idle_workers.load(Ordering::Acquire)
+        {
+            return;
+        }
+        if self.reserve_worker(self.max_size).is_some()
+        {
+            let _ignored = self.start_worker();
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Counts one more worker if there are less than `limit` . Returns the
+    //  previous number of workers.
+    //--------------------------------------------------------------------------
+    fn reserve_worker( &self, limit: usize ) -> Option<usize>
+    {
+        self.workers
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |workers|
+            {
+                (workers < limit).then_some(workers + 1)
+            })
+            .ok()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Stops counting the current worker if there are more than `size` .
+    //  Returns true if the worker must stop.
+    //--------------------------------------------------------------------------
+    fn retire_worker( &self ) -> bool
+    {
+        self.workers
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |workers|
+            {
+                (workers > self.size).then_some(workers - 1)
+            })
+            .is_ok()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Start a worker thread, already counted by `reserve_worker` .
+    //--------------------------------------------------------------------------
+    fn start_worker( self: &Arc<Self> ) -> Result<(), std::io::Error>
+    {
+        let self_clone = self.clone();
+        let num = self.next_name_num.next();
+        let name = match &self.thread_name
+        {
+            Some(thread_name) => thread_name(num),
+            None => format!("{}-{}", self.name, num),
+        };
+        self.spawn_thread(name, move ||
+        {
+            let _guard = ThreadStopGuard(self_clone.on_thread_stop.clone());
+            let mut worker_guard = WorkerGuard
+            {
+                inner: &self_clone,
+                counted: true,
+            };
+            if let Some(on_thread_start) = &self_clone.on_thread_start
+            {
+                on_thread_start();
+            }
+            worker_guard.counted = !self_clone.work(None);
+        })
+        .inspect_err(|_|
+        {
+            self.workers.fetch_sub(1, Ordering::AcqRel);
+        })
+    }
+
     //--------------------------------------------------------------------------
     //  Spawn a thread.
     //--------------------------------------------------------------------------
@@ -108,7 +184,12 @@ impl Inner
         f: impl FnOnce() + Send + 'static,
     ) -> Result<(), std::io::Error>
     {
-        std::thread::Builder::new().name(name).spawn(f)?;
+        let mut builder = std::thread::Builder::new().name(name);
+        if let Some(stack_size) = self.stack_size
+        {
+            builder = builder.stack_size(stack_size);
+        }
+        builder.spawn(f)?;
         Ok(())
     }
 
@@ -120,34 +201,215 @@ impl Inner
         Arc::strong_count(self) - 1
     }
 
+    //--------------------------------------------------------------------------
+    //  Wakes the `send` calls waiting for room in the queue.
+    //--------------------------------------------------------------------------
+    fn notify_room( &self )
+    {
+        if *self.waiting_senders.lock().unwrap() > 0
+        {
+            self.room.notify_all();
+        }
+    }
+
     //--------------------------------------------------------------------------
     //  Receive a job to run from a channel and execute it.
+    //
+    //  Helper threads pass their `HelperState` and return when they stop.
+    //  Worker threads pass `None` and return true when they retire after being
+    //  idle for `keep_alive` while the pool has more than `size` threads.
     //--------------------------------------------------------------------------
-    fn work( self: &Arc<Self> )
+    fn work( self: &Arc<Self>, stop: Option<&HelperState> ) -> bool
     {
-        loop
+        let timeout = self.keep_alive.min(Duration::from_millis(500));
+        let mut idle_since = Instant::now();
+        while !stop.is_some_and(HelperState::try_stop)
         {
+            self.idle_workers.fetch_add(1, Ordering::AcqRel);
             let recv_result = self
                 .receiver
                 .lock()
                 .unwrap()
-                .recv_timeout(Duration::from_millis(500));
- 
+                .recv_timeout(timeout);
+            self.idle_workers.fetch_sub(1, Ordering::AcqRel);
+
             //  Receive a job as a function and execute it.
             match recv_result
             {
-                Ok(f) =>
+                Ok(job) =>
                 {
+                    self.queued_jobs.fetch_sub(1, Ordering::AcqRel);
+                    self.notify_room();
                     let _ignored = self.start_threads();
-                    f();
+                    job.run();
+                    idle_since = Instant::now();
                 },
-                Err(RecvTimeoutError::Timeout) => {},
-                Err(RecvTimeoutError::Disconnected) => return,
+                Err(RecvTimeoutError::Timeout) =>
+                {
+                    if stop.is_none()
+                        && idle_since.elapsed() >= self.keep_alive
+                        && self.retire_worker()
+                    {
+                        return true;
+                    }
+                },
+                Err(RecvTimeoutError::Disconnected) => return false,
             };
 
             //  Check for dead threads and restart them.
             let _ignored = self.start_threads();
         }
+        false
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Stops counting a worker thread when it stops, including when it stops
+//  because a job panicked. Retired workers are not counted anymore.
+//------------------------------------------------------------------------------
+struct WorkerGuard<'a>
+{
+    inner: &'a Inner,
+    counted: bool,
+}
+
+impl<'a> Drop for WorkerGuard<'a>
+{
+    fn drop( &mut self )
+    {
+        if self.counted
+        {
+            self.inner.workers.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  A job that is allocated once and shared, such as a spawned task. Scheduling
+//  it does not allocate.
+//------------------------------------------------------------------------------
+pub trait Runnable: Send + Sync
+{
+    fn run( self: Arc<Self> );
+}
+
+
+//------------------------------------------------------------------------------
+//  Job sent to the worker threads.
+//------------------------------------------------------------------------------
+enum Job
+{
+    Boxed(Box<dyn FnOnce() + Send>),
+    Shared(Arc<dyn Runnable>),
+}
+
+impl Job
+{
+    fn run( self )
+    {
+        match self
+        {
+            Job::Boxed(f) => f(),
+            Job::Shared(runnable) => runnable.run(),
+        }
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Calls the `on_thread_stop` hook when the worker thread stops, including
+//  when it stops because a job panicked.
+//------------------------------------------------------------------------------
+struct ThreadStopGuard(Option<Arc<ThreadHook>>);
+
+impl Drop for ThreadStopGuard
+{
+    fn drop( &mut self )
+    {
+        if let Some(on_thread_stop) = &self.0
+        {
+            on_thread_stop();
+        }
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  State of a helper thread. A helper thread asked to stop keeps running jobs
+//  until it checks its state, and is resumed instead of a new thread if
+//  another helper thread is needed before that.
+//------------------------------------------------------------------------------
+const HELPER_RUNNING: u8 = 0;
+const HELPER_STOPPING: u8 = 1;
+const HELPER_STOPPED: u8 = 2;
+
+struct HelperState(AtomicU8);
+
+impl HelperState
+{
+    //--------------------------------------------------------------------------
+    //  Returns true if the helper thread was asked to stop, and is now
+    //  stopped.
+    //--------------------------------------------------------------------------
+    fn try_stop( &self ) -> bool
+    {
+        self.transition(HELPER_STOPPING, HELPER_STOPPED)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns true if the helper thread was asked to stop and has not stopped
+    //  yet, and is now running again.
+    //--------------------------------------------------------------------------
+    fn try_resume( &self ) -> bool
+    {
+        self.transition(HELPER_STOPPING, HELPER_RUNNING)
+    }
+
+    fn transition( &self, current: u8, new: u8 ) -> bool
+    {
+        self.0
+            .compare_exchange(current, new, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Guard returned by `ThreadPool::start_helper_thread` . On drop, the helper
+//  thread stops after its current job, unless it is reused first.
+//------------------------------------------------------------------------------
+pub struct HelperThread
+{
+    state: Arc<HelperState>,
+
+    //  Weak, so that the guard does not count as a live thread.
+    inner: Weak<Inner>,
+}
+
+impl Drop for HelperThread
+{
+    fn drop( &mut self )
+    {
+        self.state.0.store(HELPER_STOPPING, Ordering::Release);
+        if let Some(inner) = self.inner.upgrade()
+        {
+            let mut stopping = inner.stopping_helpers.lock().unwrap();
+            stopping.retain(|state|
+            {
+                state.0.load(Ordering::Acquire) != HELPER_STOPPED
+            });
+            stopping.push(self.state.clone());
+        }
+    }
+}
+
+impl Debug for HelperThread
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), core::fmt::Error>
+    {
+        write!(f, "HelperThread")
     }
 }
 
@@ -162,12 +424,16 @@ impl Inner
 //  If your threadpool load is bursty and you want to automatically recover from
 //  an all-threads-panicked state, you could
 //
+//  When `max_size` is larger than `size` , the pool starts another thread
+//  each time a job is added while no thread is idle, up to `max_size` . The
+//  threads above `size` stop after being idle for `keep_alive` .
+//
 //  After drop, threads stop as they become idle.
 //------------------------------------------------------------------------------
 pub struct ThreadPool
 {
     inner: Arc<Inner>,
-    sender: SyncSender<Box<dyn FnOnce() + Send>>,
+    sender: SyncSender<Job>,
 }
 
 impl ThreadPool
@@ -187,41 +453,16 @@ impl ThreadPool
         size: usize,
     ) -> Result<Self, NewThreadPoolError>
     {
-        if name.is_empty()
-        {
-            return Err(NewThreadPoolError::Parameter
-            (
-                "ThreadPool::new called with empty name".to_string(),
-            ));
-        }
-
-        if size < 1
-        {
-            return Err(NewThreadPoolError::Parameter(format!
-            (
-                "ThreadPool::new called with invalid size value: {:?}",
-                size
-            )));
-        }
-
-        //  Use a channel with bounded size.
-        //  If the channel was unbounded, the process could OOM (Out-Of-Memory)
-        //  when throughput goes down.
-        let (sender, receiver) = std::sync::mpsc::sync_channel(size * 200);
-        let pool = ThreadPool
-        {
-            inner: Arc::new(Inner
-            {
-                name,
-                next_name_num: AtomicCounter::new(),
-                size,
-                receiver: Mutex::new(receiver),
-            }),
-            sender,
-        };
+        ThreadPoolBuilder::new().name(name).size(size).build()
+    }
 
-        pool.inner.start_threads()?;
-        Ok(pool)
+    //--------------------------------------------------------------------------
+    //  Returns a `ThreadPoolBuilder` to configure a new threadpool.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn builder() -> ThreadPoolBuilder
+    {
+        ThreadPoolBuilder::new()
     }
 
     //--------------------------------------------------------------------------
@@ -233,6 +474,25 @@ impl ThreadPool
         self.inner.size
     }
 
+    //--------------------------------------------------------------------------
+    //  Returns the maximum number of threads the pool can grow to.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn max_size( &self ) -> usize
+    {
+        self.inner.max_size
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the number of worker threads, including the threads started
+    //  above `size` that have not retired yet.
+    //--------------------------------------------------------------------------
+    #[must_use]
+    pub fn num_workers( &self ) -> usize
+    {
+        self.inner.workers.load(Ordering::Acquire)
+    }
+
     //--------------------------------------------------------------------------
     //  Returns the number of threads currently alive.
     //--------------------------------------------------------------------------
@@ -246,12 +506,31 @@ impl ThreadPool
     //  Adds a job to the queue. The next idle thread will execute it. Jobs are
     //  started in FIFO order.
     //
-    //  When the queue is full, try again untill more jobs can be added.
+    //  When the queue is full, blocks the current thread until a thread takes
+    //  a job from the queue. Use `try_schedule` to never block.
     //--------------------------------------------------------------------------
     pub fn schedule<F: FnOnce() + Send + 'static>( &self, f: F )
     {
-        type OptBox = Option<Box<dyn FnOnce() + Send + 'static>>;
-        let mut opt_box_f: OptBox = Some(Box::new(f));
+        self.send(Job::Boxed(Box::new(f)));
+    }
+
+    //--------------------------------------------------------------------------
+    //  Adds a shared job to the queue. Same as `schedule` , but does not
+    //  allocate.
+    //--------------------------------------------------------------------------
+    pub fn schedule_runnable( &self, runnable: Arc<dyn Runnable> )
+    {
+        self.send(Job::Shared(runnable));
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sends `job` to the threads. While the queue is full, waits until a
+    //  thread takes a job from it, or for at most 100 milliseconds in case all
+    //  the threads stopped.
+    //--------------------------------------------------------------------------
+    fn send( &self, job: Job )
+    {
+        let mut opt_job = Some(job);
 
         loop
         {
@@ -266,16 +545,54 @@ impl ThreadPool
                 }
             }
 
-            //  Send job to thread via channel.
-            opt_box_f = match self.sender.try_send(opt_box_f.take().unwrap())
+            //  Send job to thread via channel. Retried while counted as
+            //  waiting, so that a thread taking a job in between notifies.
+            let mut waiting = self.inner.waiting_senders.lock().unwrap();
+            *waiting += 1;
+            opt_job = match self.try_send(opt_job.take().unwrap())
             {
-                Ok(()) => return,
+                Ok(()) => None,
                 Err(TrySendError::Disconnected(_)) => unreachable!(),
-                Err(TrySendError::Full(box_f)) => Some(box_f),
+                Err(TrySendError::Full(job)) => Some(job),
             };
+            if opt_job.is_some()
+            {
+                waiting = self
+                    .inner
+                    .room
+                    .wait_timeout(waiting, Duration::from_millis(100))
+                    .unwrap()
+                    .0;
+            }
+            *waiting -= 1;
+            if opt_job.is_none()
+            {
+                return;
+            }
+        }
+    }
 
-            //  If the channel is full, wait for a bit and retry.
-            sleep_ms(10);
+    //--------------------------------------------------------------------------
+    //  Sends `job` to the threads if the queue is not full, and starts another
+    //  thread if all threads are busy.
+    //--------------------------------------------------------------------------
+    fn try_send( &self, job: Job ) -> Result<(), TrySendError<Job>>
+    {
+        //  Counted before sending so that the thread receiving the job never
+        //  sees a count of zero.
+        self.inner.queued_jobs.fetch_add(1, Ordering::AcqRel);
+        match self.sender.try_send(job)
+        {
+            Ok(()) =>
+            {
+                self.inner.grow();
+                Ok(())
+            },
+            Err(e) =>
+            {
+                self.inner.queued_jobs.fetch_sub(1, Ordering::AcqRel);
+                Err(e)
+            },
         }
     }
 
@@ -290,9 +607,9 @@ impl ThreadPool
         f: F
     ) -> Result<(), TryScheduleError>
     {
-        match self.sender.try_send(Box::new(f))
+        match self.try_send(Job::Boxed(Box::new(f)))
         {
-            Ok(_) => {},
+            Ok(()) => {},
             Err(TrySendError::Disconnected(_)) => unreachable!(),
             Err(TrySendError::Full(_)) =>
             {
@@ -303,6 +620,70 @@ impl ThreadPool
         self.inner.start_threads().map_err(std::convert::Into::into)
     }
 
+    //--------------------------------------------------------------------------
+    //  Same as `try_schedule` , but the job is a shared `Runnable` .
+    //--------------------------------------------------------------------------
+    pub fn try_schedule_runnable
+    (
+        &self,
+        runnable: Arc<dyn Runnable>,
+    ) -> Result<(), TryScheduleError>
+    {
+        match self.try_send(Job::Shared(runnable))
+        {
+            Ok(()) => {},
+            Err(TrySendError::Disconnected(_)) => unreachable!(),
+            Err(TrySendError::Full(_)) =>
+            {
+                return Err(TryScheduleError::QueueFull)
+            },
+        };
+
+        self.inner.start_threads().map_err(std::convert::Into::into)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Starts an extra thread that executes jobs until the returned
+    //  `HelperThread` drops. Use this to keep the throughput of the pool while
+    //  a job blocks one of its threads.
+    //
+    //  Reuses a helper thread that was asked to stop but has not stopped yet,
+    //  if any.
+    //--------------------------------------------------------------------------
+    pub fn start_helper_thread( &self ) -> Result<HelperThread, std::io::Error>
+    {
+        let helper = |state| HelperThread
+        {
+            state,
+            inner: Arc::downgrade(&self.inner),
+        };
+        loop
+        {
+            let state = self.inner.stopping_helpers.lock().unwrap().pop();
+            match state
+            {
+                Some(state) if state.try_resume() => return Ok(helper(state)),
+                Some(_) => {},
+                None => break,
+            }
+        }
+
+        let state = Arc::new(HelperState(AtomicU8::new(HELPER_RUNNING)));
+        let state_clone = state.clone();
+        let inner = self.inner.clone();
+        let name = format!("{}-helper", self.inner.name);
+        self.inner.spawn_thread(name, move ||
+        {
+            let _guard = ThreadStopGuard(inner.on_thread_stop.clone());
+            if let Some(on_thread_start) = &inner.on_thread_start
+            {
+                on_thread_start();
+            }
+            inner.work(Some(&state_clone));
+        })?;
+        Ok(helper(state))
+    }
+
     //--------------------------------------------------------------------------
     //  Consumes the thread pool and waits for all threads to stop.
     //--------------------------------------------------------------------------
@@ -0,0 +1,425 @@
+/*
+
+    Clock read by the timers.
+
+    Tasks read the clock of the executor that runs them, and other threads
+    read the system clock. A virtual clock follows the system clock until it
+    is paused. While paused, its time only moves with `advance` , or, if auto
+    advance is enabled, to the next timer when all the tasks of the executors
+    that use it are idle. Sleeps in tests then complete at once, in the same
+    order as with the system clock.
+
+    ```rust
+    use core::time::Duration;
+    use wexing::timer::Clock;
+
+    let clock = Clock::new_virtual();
+    clock.pause();
+    let executor = wexing::executor::Executor::builder()
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    let start = clock.now();
+    executor.block_on(async move
+    {
+        wexing::timer::sleep_for(Duration::from_secs(3600)).await;
+    });
+    assert_eq!(clock.now() - start, Duration::from_secs(3600));
+    ```
+
+*/
+
+use crate::executor::get_thread_executor;
+use crate::timer::error::TimerThreadNotStarted;
+use crate::timer::{ Timer, TIMER };
+
+use core::fmt::{ Debug, Formatter };
+use core::time::Duration;
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::sync::{ Arc, Mutex };
+use std::time::Instant;
+
+
+//------------------------------------------------------------------------------
+//  Returns the current time on the clock of the current task, or on the
+//  system clock outside tasks.
+//------------------------------------------------------------------------------
+pub fn now() -> Instant
+{
+    current().now()
+}
+
+
+//------------------------------------------------------------------------------
+//  Returns the clock of the executor of the current thread.
+//------------------------------------------------------------------------------
+pub(crate) fn current() -> Clock
+{
+    get_thread_executor().map(|executor| executor.clock().clone())
+        .unwrap_or_default()
+}
+
+
+//------------------------------------------------------------------------------
+//  Clock of the timers: the system clock, or a virtual clock.
+//------------------------------------------------------------------------------
+#[derive(Clone, Default)]
+pub struct Clock
+{
+    handle: Option<Arc<VirtualHandle>>,
+}
+
+impl Clock
+{
+    //--------------------------------------------------------------------------
+    //  Returns the system clock. Its timers need `start_timer_thread` .
+    //--------------------------------------------------------------------------
+    pub fn system() -> Self
+    {
+        Self { handle: None }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Creates a virtual clock, starting at the current time of the system
+    //  clock. It has its own timer thread.
+    //--------------------------------------------------------------------------
+    pub fn new_virtual() -> Self
+    {
+        let now = Instant::now();
+        let shared = Arc::new(VirtualClock
+        {
+            timer: Arc::new(Timer::new(now)),
+            state: Mutex::new(VirtualState
+            {
+                base: now,
+                resumed_at: now,
+                paused: false,
+                auto_advance: true,
+            }),
+            busy: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+        });
+        let thread_shared = shared.clone();
+        std::thread::Builder::new()
+            .name("timer-virtual".to_string())
+            .spawn(move || virtual_timer_thread(&thread_shared))
+            .unwrap();
+        Self { handle: Some(Arc::new(VirtualHandle { shared })) }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns true if this is a virtual clock.
+    //--------------------------------------------------------------------------
+    pub fn is_virtual( &self ) -> bool
+    {
+        self.handle.is_some()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the current time.
+    //--------------------------------------------------------------------------
+    pub fn now( &self ) -> Instant
+    {
+        match self.shared()
+        {
+            Some(shared) => shared.now(),
+            None => Instant::now(),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns true if the clock is paused. The system clock is never paused.
+    //--------------------------------------------------------------------------
+    pub fn is_paused( &self ) -> bool
+    {
+        self.shared()
+            .is_some_and(|shared| shared.state.lock().unwrap().paused)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Stops the time. The time is rounded up to the next millisecond, the
+    //  resolution of the timers, so that timers set while paused expire at
+    //  their exact instant. Panics with the system clock.
+    //--------------------------------------------------------------------------
+    pub fn pause( &self )
+    {
+        let shared = self.expect_virtual();
+        let mut state = shared.state.lock().unwrap();
+        if !state.paused
+        {
+            let timer = &shared.timer;
+            state.base = timer.instant_at(timer.tick_for(state.now()));
+            state.paused = true;
+        }
+        drop(state);
+        shared.timer.notify();
+    }
+
+    //--------------------------------------------------------------------------
+    //  Restarts the time from where it was paused. Panics with the system
+    //  clock.
+    //--------------------------------------------------------------------------
+    pub fn resume( &self )
+    {
+        let shared = self.expect_virtual();
+        let mut state = shared.state.lock().unwrap();
+        if state.paused
+        {
+            state.resumed_at = Instant::now();
+            state.paused = false;
+        }
+        drop(state);
+        shared.timer.notify();
+    }
+
+    //--------------------------------------------------------------------------
+    //  Moves the time forward by `duration` and wakes the timers that expire
+    //  on the way before returning. Panics unless the clock is paused.
+    //--------------------------------------------------------------------------
+    pub fn advance( &self, duration: Duration )
+    {
+        let shared = self.expect_virtual();
+        let mut state = shared.state.lock().unwrap();
+        assert!(state.paused, "the clock must be paused to advance it");
+        state.base += duration;
+        let now = state.base;
+        drop(state);
+
+        //  Other threads may advance the wheels at the same time. Each wheel is
+        //  locked while it advances, so each entry expires once.
+        shared.timer.fire(now, &mut Vec::new());
+        shared.timer.notify();
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets whether the paused clock moves to the next timer by itself when
+    //  all the tasks are idle. Enabled by default. Panics with the system
+    //  clock.
+    //--------------------------------------------------------------------------
+    pub fn set_auto_advance( &self, enabled: bool )
+    {
+        let shared = self.expect_virtual();
+        shared.state.lock().unwrap().auto_advance = enabled;
+        shared.timer.notify();
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the number of scheduled wakes that have neither expired nor
+    //  been cancelled.
+    //--------------------------------------------------------------------------
+    pub fn pending_timers( &self ) -> usize
+    {
+        match self.shared()
+        {
+            Some(shared) => shared.timer.len(),
+            None => TIMER.get().map_or(0, |timer| timer.len()),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the timer of the clock.
+    //--------------------------------------------------------------------------
+    pub(super) fn timer( &self ) -> Result<Arc<Timer>, TimerThreadNotStarted>
+    {
+        match self.shared()
+        {
+            Some(shared) => Ok(shared.timer.clone()),
+            None => TIMER.get().cloned().ok_or(TimerThreadNotStarted {}),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Counts a task that is queued or running. A paused clock does not auto
+    //  advance while tasks are counted. Does nothing with the system clock.
+    //--------------------------------------------------------------------------
+    pub(crate) fn add_busy( &self )
+    {
+        if let Some(shared) = self.shared()
+        {
+            shared.busy.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Stops counting a task counted by `add_busy` .
+    //--------------------------------------------------------------------------
+    pub(crate) fn sub_busy( &self )
+    {
+        if let Some(shared) = self.shared()
+        {
+            if shared.busy.fetch_sub(1, Ordering::SeqCst) == 1
+            {
+                shared.timer.notify();
+            }
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Counts the current thread, or a job, as busy until the guard is
+    //  dropped.
+    //--------------------------------------------------------------------------
+    pub(crate) fn enter_busy( &self ) -> BusyGuard
+    {
+        self.add_busy();
+        BusyGuard { clock: self.clone() }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns a guard that stops counting a job already counted by
+    //  `add_busy` on drop.
+    //--------------------------------------------------------------------------
+    pub(crate) fn take_busy( &self ) -> BusyGuard
+    {
+        BusyGuard { clock: self.clone() }
+    }
+
+    fn shared( &self ) -> Option<&VirtualClock>
+    {
+        self.handle.as_ref().map(|handle| &*handle.shared)
+    }
+
+    fn expect_virtual( &self ) -> &VirtualClock
+    {
+        self.shared().expect("the system clock cannot be paused or advanced")
+    }
+}
+
+impl Debug for Clock
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> std::fmt::Result
+    {
+        match self.is_virtual()
+        {
+            true => write!(f, "Clock::Virtual{{paused={}}}", self.is_paused()),
+            false => write!(f, "Clock::System"),
+        }
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Guard returned by `Clock::enter_busy` .
+//------------------------------------------------------------------------------
+pub(crate) struct BusyGuard
+{
+    clock: Clock,
+}
+
+impl Drop for BusyGuard
+{
+    fn drop( &mut self )
+    {
+        self.clock.sub_busy();
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Time of a virtual clock. While running, the time is `base` plus the time
+//  elapsed on the system clock since `resumed_at` . While paused, it is
+//  `base` .
+//------------------------------------------------------------------------------
+struct VirtualState
+{
+    base: Instant,
+    resumed_at: Instant,
+    paused: bool,
+    auto_advance: bool,
+}
+
+impl VirtualState
+{
+    fn now( &self ) -> Instant
+    {
+        match self.paused
+        {
+            true => self.base,
+            false => self.base + self.resumed_at.elapsed(),
+        }
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  State of a virtual clock shared with its timer thread. `busy` counts the
+//  tasks queued or running on the executors that use the clock.
+//------------------------------------------------------------------------------
+struct VirtualClock
+{
+    timer: Arc<Timer>,
+    state: Mutex<VirtualState>,
+    busy: AtomicUsize,
+    closed: AtomicBool,
+}
+
+impl VirtualClock
+{
+    fn now( &self ) -> Instant
+    {
+        self.state.lock().unwrap().now()
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Handle of a virtual clock held by the `Clock` values. The timer thread only
+//  holds the shared state, so it stops once the last `Clock` is dropped.
+//------------------------------------------------------------------------------
+struct VirtualHandle
+{
+    shared: Arc<VirtualClock>,
+}
+
+impl Drop for VirtualHandle
+{
+    fn drop( &mut self )
+    {
+        self.shared.closed.store(true, Ordering::SeqCst);
+        self.shared.timer.notify();
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Timer thread of a virtual clock. While the clock runs, it sleeps on the
+//  system clock like the timer thread of the system clock. While it is
+//  paused, it sleeps until it is notified, or moves the clock to the next
+//  timer if auto advance is enabled and no task is busy.
+//------------------------------------------------------------------------------
+fn virtual_timer_thread( shared: &VirtualClock )
+{
+    let timer = &shared.timer;
+    let mut expired = Vec::new();
+    loop
+    {
+        let next = timer.fire(shared.now(), &mut expired);
+
+        //  Everything that changes the decision below notifies the thread
+        //  with `sleep_lock` held, so nothing is missed while deciding.
+        let guard = timer.sleep_lock.lock().unwrap();
+        if shared.closed.load(Ordering::SeqCst)
+        {
+            return;
+        }
+        let until = timer.sleep_target(next);
+        let mut state = shared.state.lock().unwrap();
+        let now = timer.tick_at(state.now());
+        if !state.paused
+        {
+            drop(state);
+            timer.wait(guard, until, now);
+        }
+        else if state.auto_advance
+            && until != u64::MAX
+            && shared.busy.load(Ordering::SeqCst) == 0
+        {
+            state.base = state.base.max(timer.instant_at(until));
+        }
+        else
+        {
+            drop(state);
+            timer.wait(guard, u64::MAX, now);
+        }
+    }
+}
@@ -4,7 +4,9 @@
 
 */
 
-use crate::timer::schedule_wake;
+use crate::executor::coop;
+use crate::timer::{ cancel_wake, now, reschedule_wake, schedule_wake };
+use crate::timer::TimerKey;
 use crate::timer::error::{ DeadlineError, DeadlineExceeded };
 
 use core::future::Future;
@@ -46,18 +48,21 @@ pub async fn with_timeout<Fut: Future>
     duration: Duration,
 ) -> Result<Fut::Output, DeadlineExceeded>
 {
-    with_deadline(inner, Instant::now() + duration).await
+    with_deadline(inner, now() + duration).await
 }
 
 
 //------------------------------------------------------------------------------
-//  Future that monitors whether the task is completed by the deadline.
+//  Future that monitors whether the task is completed by the deadline. The
+//  scheduled wake is cancelled when `inner` completes or the future is
+//  dropped.
 //------------------------------------------------------------------------------
 pub struct DeadlineFuture<Fut: Future + Unpin>
 {
     inner: Fut,
     deadline: Instant,
     waker: Arc<Mutex<Option<Waker>>>,
+    key: Option<TimerKey>,
 }
 
 impl<Fut: Future + Unpin> DeadlineFuture<Fut>
@@ -72,8 +77,49 @@ impl<Fut: Future + Unpin> DeadlineFuture<Fut>
             inner,
             deadline,
             waker: Arc::new(Mutex::new(None)),
+            key: None,
         }
     }
+
+    //--------------------------------------------------------------------------
+    //  Returns the instant after which the future returns `DeadlineExceeded` .
+    //--------------------------------------------------------------------------
+    pub fn deadline( &self ) -> Instant
+    {
+        self.deadline
+    }
+
+    //--------------------------------------------------------------------------
+    //  Changes the deadline. The scheduled wake is moved rather than scheduled
+    //  again.
+    //--------------------------------------------------------------------------
+    pub fn reset( &mut self, deadline: Instant )
+    {
+        self.deadline = deadline;
+        if let Some(key) = self.key.take()
+        {
+            self.key = reschedule_wake(&key, deadline);
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Cancels the scheduled wake, if any.
+    //--------------------------------------------------------------------------
+    fn cancel_wake( &mut self )
+    {
+        if let Some(key) = self.key.take()
+        {
+            cancel_wake(&key);
+        }
+    }
+}
+
+impl<Fut: Future + Unpin> Drop for DeadlineFuture<Fut>
+{
+    fn drop( &mut self )
+    {
+        self.cancel_wake();
+    }
 }
 
 impl<Fut: Future + Unpin> Future for DeadlineFuture<Fut>
@@ -88,17 +134,27 @@ impl<Fut: Future + Unpin> Future for DeadlineFuture<Fut>
         cx: &mut Context<'_>
     ) -> Poll<Self::Output>
     {
-        //  If the schedule datetime is in the past, returns `DeadlineExceeded`
-        //  immediately.
-        if self.deadline < Instant::now()
+        //  If the schedule datetime has come on the clock, returns
+        //  `DeadlineExceeded` immediately. Only this consumes budget here, as
+        //  `inner` consumes its own.
+        if self.deadline <= now()
         {
+            let Poll::Ready(coop) = coop::poll_proceed(cx) else
+            {
+                return Poll::Pending;
+            };
+            coop.made_progress();
             return Poll::Ready(Err(DeadlineError::DeadlineExceeded));
         }
 
         //  Polls `inner` and if finished the task, returns `Poll::Ready` .
         match Pin::new(&mut self.inner).poll(cx)
         {
-            Poll::Ready(r) => return Poll::Ready(Ok(r)),
+            Poll::Ready(r) =>
+            {
+                self.cancel_wake();
+                return Poll::Ready(Ok(r));
+            },
             Poll::Pending => {},
         }
 
@@ -106,7 +162,7 @@ impl<Fut: Future + Unpin> Future for DeadlineFuture<Fut>
         let old_waker = self.waker.lock().unwrap().replace(cx.waker().clone());
         if old_waker.is_none()
         {
-            schedule_wake(self.deadline, self.waker.clone())
+            self.key = schedule_wake(self.deadline, self.waker.clone())
                 .map_err(|_| DeadlineError::TimerThreadNotStarted)?;
         }
 
@@ -0,0 +1,165 @@
+/*
+
+    A timer that ticks at a fixed period.
+
+*/
+
+use crate::stream::Stream;
+use crate::timer::{ now, SleepFuture };
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{ Context, Poll };
+use core::time::Duration;
+use std::time::Instant;
+
+
+//------------------------------------------------------------------------------
+//  A tick is missed if it is polled this late. Below this, the timer latency
+//  is not counted as a missed tick.
+//------------------------------------------------------------------------------
+const MISSED_TICK_TOLERANCE: Duration = Duration::from_millis(5);
+
+
+//------------------------------------------------------------------------------
+//  Returns an `Interval` that ticks now and then every `period` .
+//------------------------------------------------------------------------------
+pub fn interval( period: Duration ) -> Interval
+{
+    interval_at(now(), period)
+}
+
+
+//------------------------------------------------------------------------------
+//  Returns an `Interval` that ticks at `start` and then every `period` .
+//------------------------------------------------------------------------------
+pub fn interval_at( start: Instant, period: Duration ) -> Interval
+{
+    assert!(!period.is_zero(), "period must be greater than zero");
+    Interval
+    {
+        period,
+        missed_tick_behavior: MissedTickBehavior::default(),
+        sleep: SleepFuture::new(start),
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  What an `Interval` does with the ticks it missed because it was not polled
+//  in time.
+//
+//  - `Burst` : ticks as fast as possible until it catches up with the
+//    schedule.
+//  - `Delay` : ticks once, and schedules the next tick `period` from now.
+//  - `Skip` : ticks once, and schedules the next tick at the next multiple of
+//    `period` on the original schedule.
+//
+//  A tick is missed if it is polled more than `MISSED_TICK_TOLERANCE` late.
+//------------------------------------------------------------------------------
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum MissedTickBehavior
+{
+    #[default]
+    Burst,
+    Delay,
+    Skip,
+}
+
+
+//------------------------------------------------------------------------------
+//  Timer that ticks every `period` . Each tick returns the instant it was
+//  scheduled at.
+//------------------------------------------------------------------------------
+pub struct Interval
+{
+    period: Duration,
+    missed_tick_behavior: MissedTickBehavior,
+    sleep: SleepFuture,
+}
+
+impl Interval
+{
+    //--------------------------------------------------------------------------
+    //  Waits until the next tick.
+    //--------------------------------------------------------------------------
+    pub async fn tick( &mut self ) -> Instant
+    {
+        core::future::poll_fn(|cx| self.poll_tick(cx)).await
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns `Poll::Ready` with the scheduled instant if the next tick has
+    //  come, and schedules the tick after it.
+    //--------------------------------------------------------------------------
+    pub fn poll_tick( &mut self, cx: &mut Context<'_> ) -> Poll<Instant>
+    {
+        match Pin::new(&mut self.sleep).poll(cx)
+        {
+            Poll::Ready(result) => result.unwrap(),
+            Poll::Pending => return Poll::Pending,
+        }
+
+        let tick = self.sleep.deadline();
+        let now = now();
+        let missed =
+            now.saturating_duration_since(tick) > MISSED_TICK_TOLERANCE;
+        let next = match self.missed_tick_behavior
+        {
+            _ if !missed => tick + self.period,
+            MissedTickBehavior::Burst => tick + self.period,
+            MissedTickBehavior::Delay => now + self.period,
+            MissedTickBehavior::Skip =>
+            {
+                let missed_ticks = (now - tick).as_nanos()
+                    / self.period.as_nanos();
+                let missed_ticks =
+                    u32::try_from(missed_ticks).unwrap_or(u32::MAX - 1);
+                tick + self.period * (missed_ticks + 1)
+            },
+        };
+        self.sleep.reset(next);
+        Poll::Ready(tick)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the period of the ticks.
+    //--------------------------------------------------------------------------
+    pub fn period( &self ) -> Duration
+    {
+        self.period
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the behavior for the missed ticks.
+    //--------------------------------------------------------------------------
+    pub fn missed_tick_behavior( &self ) -> MissedTickBehavior
+    {
+        self.missed_tick_behavior
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sets the behavior for the missed ticks.
+    //--------------------------------------------------------------------------
+    pub fn set_missed_tick_behavior( &mut self, behavior: MissedTickBehavior )
+    {
+        self.missed_tick_behavior = behavior;
+    }
+}
+
+impl Stream for Interval
+{
+    type Item = Instant;
+
+    //--------------------------------------------------------------------------
+    //  Never finishes.
+    //--------------------------------------------------------------------------
+    fn poll_next
+    (
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<Option<Self::Item>>
+    {
+        self.get_mut().poll_tick(cx).map(Some)
+    }
+}
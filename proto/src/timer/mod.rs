@@ -46,155 +46,391 @@
     ).await??;
     ```
 
+    ```rust
+    use core::time::Duration;
+    use wexing::timer::MissedTickBehavior;
+
+    async fn send_heartbeat() {}
+
+    wexing::timer::start_timer_thread();
+    let mut interval = wexing::timer::interval(Duration::from_secs(5));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop
+    {
+        interval.tick().await;
+        send_heartbeat().await;
+    }
+    ```
+
 */
 
 mod error;
+mod clock;
 mod sleep;
 mod deadline;
+mod interval;
+mod wheel;
+pub use clock::*;
 pub use sleep::*;
 pub use deadline::*;
+pub use interval::*;
 
 use error::TimerThreadNotStarted;
 use once_cell::sync::OnceCell;
+use wheel::{ Wheel, WheelKey };
 
-use core::cmp::Reverse;
 use core::task::Waker;
 use core::fmt::Debug;
-use std::collections::BinaryHeap;
-use std::sync::mpsc::{ Receiver, RecvTimeoutError, SyncSender };
-use std::sync::{ Arc, Mutex };
+use core::time::Duration;
+use std::sync::atomic::{ AtomicU64, AtomicUsize, Ordering };
+use std::sync::{ Arc, Condvar, Mutex, MutexGuard };
 use std::time::Instant;
 
-type TimerThreadSender = OnceCell<SyncSender<ScheduledWake>>;
-
 
 //------------------------------------------------------------------------------
-//  Sender for sending tasks to the global timer thread.
+//  Timer of the system clock, shared by the timer thread and the threads that
+//  schedule wakes.
 //------------------------------------------------------------------------------
-static TIMER_THREAD_SENDER: TimerThreadSender = OnceCell::new();
+static TIMER: OnceCell<Arc<Timer>> = OnceCell::new();
 
 
 //------------------------------------------------------------------------------
 //  Starts the worker thread, if it's not already started. You must call this
-//  before calling `sleep_until` or `sleep_for` .
+//  before calling `sleep_until` or `sleep_for` with the system clock.
 //------------------------------------------------------------------------------
 pub fn start_timer_thread()
 {
-    TIMER_THREAD_SENDER.get_or_init(||
+    TIMER.get_or_init(||
     {
-        let (sender, receiver) = std::sync::mpsc::sync_channel(0);
+        let timer = Arc::new(Timer::new(Instant::now()));
+        let thread_timer = timer.clone();
         std::thread::Builder::new()
             .name("timer".to_string())
-            .spawn(|| timer_thread(receiver))
+            .spawn(move || timer_thread(&thread_timer))
             .unwrap();
-        sender
+        timer
     });
 }
 
-fn timer_thread( receiver: Receiver<ScheduledWake> )
+//------------------------------------------------------------------------------
+//  Advances the wheels to the current tick, wakes the expired entries in a
+//  batch, and sleeps until the next expiration or an earlier registration.
+//------------------------------------------------------------------------------
+fn timer_thread( timer: &Timer )
 {
-    let mut heap: BinaryHeap<Reverse<ScheduledWake>> = BinaryHeap::new();
+    let mut expired = Vec::new();
     loop
     {
-        //  Takes the top of the heap ordered by scheduled datetime and compares
-        //  it to the current datetime.
-        if let Some(Reverse(peeked_wake)) = heap.peek()
-        {
-            let now = Instant::now();
-            if peeked_wake.instant < now
-            {
-                //  Calls `wake()` if the scheduled datetime is exceeded.
-                heap.pop().unwrap().0.wake();
-            }
-            else
-            {
-                //  Waits until the next scheduled datetime, but if the receiver
-                //  receives a new task on the way, updates the heap and resumes
-                //  processing.
-                match receiver.recv_timeout
-                (
-                    peeked_wake.instant.saturating_duration_since(now)
-                )
-                {
-                    Ok(new_wake) => { heap.push(Reverse(new_wake)); },
-                    Err(RecvTimeoutError::Timeout) => {},
-                    Err(RecvTimeoutError::Disconnected) => unreachable!(),
-                }
-            }
-        }
-        else
-        {
-            //  Locks this thread until task is received.
-            heap.push(Reverse(receiver.recv().unwrap()));
-        }
+        let next = timer.fire(Instant::now(), &mut expired);
+        let guard = timer.sleep_lock.lock().unwrap();
+        let until = timer.sleep_target(next);
+        timer.wait(guard, until, timer.tick_at(Instant::now()));
     }
 }
 
 
 //------------------------------------------------------------------------------
-//  Schedules a `wake()` call on a timer thread.
+//  Returns the number of scheduled wakes of the current clock that have
+//  neither expired nor been cancelled.
+//------------------------------------------------------------------------------
+pub fn pending_timers() -> usize
+{
+    clock::current().pending_timers()
+}
+
+
+//------------------------------------------------------------------------------
+//  Schedules a `wake()` call on the timer of the current clock. Returns the key
+//  to cancel it, or `None` if `instant` has already elapsed and the waker has
+//  been woken.
 //------------------------------------------------------------------------------
 fn schedule_wake
 (
     instant: Instant,
     waker: Arc<Mutex<Option<Waker>>>,
-) -> Result<(), TimerThreadNotStarted>
+) -> Result<Option<TimerKey>, TimerThreadNotStarted>
 {
-    let sender = TIMER_THREAD_SENDER.get().ok_or(TimerThreadNotStarted {})?;
-    sender.send(ScheduledWake { instant, waker }).unwrap();
-    Ok(())
+    let timer = clock::current().timer()?;
+    Ok(timer.register(instant, ScheduledWake { waker }))
 }
 
 
 //------------------------------------------------------------------------------
-//  A structure for executing a scheduled `wake()` . `instant` contains the
-//  scheduled datetime. The timer thread compares the scheduled datetime with
-//  the current datetime, and if the scheduled datetime is earlier than the
-//  current datetime, `wake` is called.
+//  Cancels a scheduled `wake()` call. Does nothing if it has already expired.
 //------------------------------------------------------------------------------
-#[derive(Debug)]
-pub(crate) struct ScheduledWake
+fn cancel_wake( key: &TimerKey )
 {
-    instant: Instant,
-    waker: Arc<Mutex<Option<Waker>>>,
+    let removed = key.timer.wheels[key.wheel].lock().unwrap().remove(&key.key);
+    drop(removed);
 }
 
-impl ScheduledWake
+
+//------------------------------------------------------------------------------
+//  Moves a scheduled `wake()` call to `instant` . Returns the new key, or
+//  `None` if the wake has already been called.
+//------------------------------------------------------------------------------
+fn reschedule_wake( key: &TimerKey, instant: Instant ) -> Option<TimerKey>
+{
+    key.timer.reschedule(key, instant)
+}
+
+
+//------------------------------------------------------------------------------
+//  Key of a scheduled wake: the timer and the wheel that hold it, and its key
+//  in the wheel.
+//------------------------------------------------------------------------------
+pub(crate) struct TimerKey
+{
+    timer: Arc<Timer>,
+    wheel: usize,
+    key: WheelKey,
+}
+
+
+//------------------------------------------------------------------------------
+//  Timer. Time is counted in ticks of 1 millisecond since `start` , on the
+//  clock that owns the timer.
+//
+//  The entries are spread over one wheel per CPU, so that the worker threads
+//  rarely contend for the lock of a wheel. `next_wake` is the tick the timer
+//  thread sleeps until. A registration that expires earlier lowers it and
+//  wakes the timer thread.
+//------------------------------------------------------------------------------
+struct Timer
+{
+    start: Instant,
+    wheels: Box<[Mutex<Wheel<ScheduledWake>>]>,
+    next_wake: AtomicU64,
+    sleep_lock: Mutex<()>,
+    sleep_cond: Condvar,
+}
+
+impl Timer
 {
     //--------------------------------------------------------------------------
-    //  Calls the `wake()` of the inner waker.
+    //  Creates a new timer starting at `start` .
     //--------------------------------------------------------------------------
-    pub fn wake( &self )
+    fn new( start: Instant ) -> Self
     {
-        if let Some(waker) = self.waker.lock().unwrap().take()
+        let num_wheels = std::thread::available_parallelism()
+            .map_or(1, |num| num.get());
+        Self
         {
-            waker.wake();
+            start,
+            wheels: (0..num_wheels).map(|_| Mutex::new(Wheel::new())).collect(),
+            next_wake: AtomicU64::new(u64::MAX),
+            sleep_lock: Mutex::new(()),
+            sleep_cond: Condvar::new(),
         }
     }
-}
 
-impl PartialEq for ScheduledWake
-{
-    fn eq( &self, other: &Self ) -> bool
+    //--------------------------------------------------------------------------
+    //  Returns the tick of `instant` , rounded down.
+    //--------------------------------------------------------------------------
+    fn tick_at( &self, instant: Instant ) -> u64
+    {
+        let millis = instant.saturating_duration_since(self.start).as_millis();
+        u64::try_from(millis).unwrap_or(u64::MAX)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the tick of `instant` , rounded up so that no entry expires
+    //  before its instant.
+    //--------------------------------------------------------------------------
+    fn tick_for( &self, instant: Instant ) -> u64
+    {
+        let nanos = instant.saturating_duration_since(self.start).as_nanos();
+        u64::try_from(nanos.div_ceil(1_000_000)).unwrap_or(u64::MAX)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the instant at which `tick` starts.
+    //--------------------------------------------------------------------------
+    fn instant_at( &self, tick: u64 ) -> Instant
+    {
+        self.start + Duration::from_millis(tick)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the number of entries of all the wheels.
+    //--------------------------------------------------------------------------
+    fn len( &self ) -> usize
     {
-        std::cmp::PartialEq::eq(&self.instant, &other.instant)
+        self.wheels.iter().map(|wheel| wheel.lock().unwrap().len()).sum()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Advances the wheels to `now` , wakes the expired entries in a batch
+    //  outside the locks, and returns the tick of the next expiration.
+    //--------------------------------------------------------------------------
+    fn fire( &self, now: Instant, expired: &mut Vec<ScheduledWake> ) -> u64
+    {
+        //  A registration while the wheels are scanned lowers `next_wake`
+        //  again, so the timer thread does not sleep past it.
+        self.next_wake.store(u64::MAX, Ordering::SeqCst);
+        let now = self.tick_at(now);
+        let mut next = u64::MAX;
+        for wheel in self.wheels.iter()
+        {
+            let mut wheel = wheel.lock().unwrap();
+            wheel.advance(now, expired);
+            if let Some(when) = wheel.next_expiration()
+            {
+                next = next.min(when);
+            }
+        }
+
+        for scheduled_wake in expired.drain(..)
+        {
+            scheduled_wake.wake();
+        }
+        next
+    }
+
+    //--------------------------------------------------------------------------
+    //  Adds `scheduled_wake` to the wheel of the current thread, or wakes it
+    //  now if `instant` has already elapsed.
+    //--------------------------------------------------------------------------
+    fn register
+    (
+        self: &Arc<Self>,
+        instant: Instant,
+        scheduled_wake: ScheduledWake,
+    ) -> Option<TimerKey>
+    {
+        let when = self.tick_for(instant);
+        let wheel = current_wheel() % self.wheels.len();
+        let result = self.wheels[wheel].lock().unwrap()
+            .insert(when, scheduled_wake);
+        self.inserted(wheel, when, result)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Moves the entry of `key` to `instant` in the same wheel. Returns `None`
+    //  if the entry has already expired, or if `instant` has already elapsed
+    //  and the entry has been woken.
+    //--------------------------------------------------------------------------
+    fn reschedule
+    (
+        self: &Arc<Self>,
+        key: &TimerKey,
+        instant: Instant,
+    ) -> Option<TimerKey>
+    {
+        let when = self.tick_for(instant);
+        let mut wheel = self.wheels[key.wheel].lock().unwrap();
+        let scheduled_wake = wheel.remove(&key.key)?;
+        let result = wheel.insert(when, scheduled_wake);
+        drop(wheel);
+        self.inserted(key.wheel, when, result)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Completes an insertion at tick `when` into `wheel` : wakes the timer
+    //  thread if the entry expires before it wakes up, or wakes the entry now
+    //  if the wheel refused it.
+    //--------------------------------------------------------------------------
+    fn inserted
+    (
+        self: &Arc<Self>,
+        wheel: usize,
+        when: u64,
+        result: Result<WheelKey, ScheduledWake>,
+    ) -> Option<TimerKey>
+    {
+        match result
+        {
+            Ok(key) =>
+            {
+                if self.next_wake.fetch_min(when, Ordering::SeqCst) > when
+                {
+                    self.notify();
+                }
+                Some(TimerKey { timer: self.clone(), wheel, key })
+            },
+            Err(scheduled_wake) =>
+            {
+                scheduled_wake.wake();
+                None
+            },
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Wakes the timer thread.
+    //--------------------------------------------------------------------------
+    fn notify( &self )
+    {
+        let _guard = self.sleep_lock.lock().unwrap();
+        self.sleep_cond.notify_one();
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the tick the timer thread must sleep until: `next` , or an
+    //  earlier registration. Call this with `sleep_lock` held.
+    //--------------------------------------------------------------------------
+    fn sleep_target( &self, next: u64 ) -> u64
+    {
+        self.next_wake.fetch_min(next, Ordering::SeqCst).min(next)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sleeps with `sleep_lock` held as `guard` from tick `now` until tick
+    //  `until` , forever if it is `u64::MAX` , or until `notify` .
+    //--------------------------------------------------------------------------
+    fn wait( &self, guard: MutexGuard<'_, ()>, until: u64, now: u64 )
+    {
+        if until == u64::MAX
+        {
+            drop(self.sleep_cond.wait(guard).unwrap());
+        }
+        else if until > now
+        {
+            let timeout = Duration::from_millis(until - now);
+            drop(self.sleep_cond.wait_timeout(guard, timeout).unwrap());
+        }
     }
 }
 
-impl Eq for ScheduledWake {}
 
-impl PartialOrd for ScheduledWake
+//------------------------------------------------------------------------------
+//  Returns the index of the wheel of the current thread. Each thread gets the
+//  next wheel the first time it schedules a wake.
+//------------------------------------------------------------------------------
+fn current_wheel() -> usize
 {
-    fn partial_cmp( &self, other: &Self ) -> Option<core::cmp::Ordering>
+    static NEXT_WHEEL: AtomicUsize = AtomicUsize::new(0);
+    thread_local!
     {
-        std::cmp::PartialOrd::partial_cmp(&self.instant, &other.instant)
+        static WHEEL: usize = NEXT_WHEEL.fetch_add(1, Ordering::Relaxed);
     }
+    WHEEL.with(|wheel| *wheel)
+}
+
+
+//------------------------------------------------------------------------------
+//  A structure for executing a scheduled `wake()` . The timer thread calls
+//  `wake` when the tick of its entry has elapsed.
+//------------------------------------------------------------------------------
+#[derive(Debug)]
+pub(crate) struct ScheduledWake
+{
+    waker: Arc<Mutex<Option<Waker>>>,
 }
 
-impl Ord for ScheduledWake
+impl ScheduledWake
 {
-    fn cmp( &self, other: &Self ) -> core::cmp::Ordering
+    //--------------------------------------------------------------------------
+    //  Calls the `wake()` of the inner waker.
+    //--------------------------------------------------------------------------
+    pub fn wake( &self )
     {
-        std::cmp::Ord::cmp(&self.instant, &other.instant)
+        //  Releases the lock first. `wake()` may wait for room in the queue of
+        //  the executor, while a worker polling the task waits for the lock.
+        let waker = self.waker.lock().unwrap().take();
+        if let Some(waker) = waker
+        {
+            waker.wake();
+        }
     }
 }
@@ -4,7 +4,9 @@
 
 */
 
-use crate::timer::schedule_wake;
+use crate::executor::coop;
+use crate::timer::{ cancel_wake, now, reschedule_wake, schedule_wake };
+use crate::timer::TimerKey;
 use crate::timer::error::TimerThreadNotStarted;
 
 use core::future::Future;
@@ -29,17 +31,19 @@ pub async fn sleep_until( deadline: Instant )
 //------------------------------------------------------------------------------
 pub async fn sleep_for( duration: Duration )
 {
-    SleepFuture::new(Instant::now() + duration).await.unwrap();
+    SleepFuture::new(now() + duration).await.unwrap();
 }
 
 
 //------------------------------------------------------------------------------
 //  Future that sleeps for a certain period of time using a timer thread.
+//  Dropping it cancels its scheduled wake.
 //------------------------------------------------------------------------------
 pub struct SleepFuture
 {
     deadline: Instant,
     waker: Arc<Mutex<Option<Waker>>>,
+    key: Option<TimerKey>,
 }
 
 impl SleepFuture
@@ -54,6 +58,39 @@ impl SleepFuture
         {
             deadline,
             waker: Arc::new(Mutex::new(None)),
+            key: None,
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the instant the future completes at.
+    //--------------------------------------------------------------------------
+    pub fn deadline( &self ) -> Instant
+    {
+        self.deadline
+    }
+
+    //--------------------------------------------------------------------------
+    //  Changes the deadline. The scheduled wake is moved rather than scheduled
+    //  again, and the future can be awaited again after it has completed.
+    //--------------------------------------------------------------------------
+    pub fn reset( &mut self, deadline: Instant )
+    {
+        self.deadline = deadline;
+        if let Some(key) = self.key.take()
+        {
+            self.key = reschedule_wake(&key, deadline);
+        }
+    }
+}
+
+impl Drop for SleepFuture
+{
+    fn drop( &mut self )
+    {
+        if let Some(key) = self.key.take()
+        {
+            cancel_wake(&key);
         }
     }
 }
@@ -66,12 +103,22 @@ impl Future for SleepFuture
     //  Use a timer thread to return `Poll::Ready` when the scheduled datetime
     //  come.
     //--------------------------------------------------------------------------
-    fn poll( self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<Self::Output>
+    fn poll
+    (
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<Self::Output>
     {
-        //  If the schedule datetime is in the past, returns `Poll::Ready`
-        //  immediately.
-        if self.deadline < Instant::now()
+        let Poll::Ready(coop) = coop::poll_proceed(cx) else
+        {
+            return Poll::Pending;
+        };
+
+        //  If the schedule datetime has come on the clock, returns
+        //  `Poll::Ready` immediately.
+        if self.deadline <= now()
         {
+            coop.made_progress();
             return Poll::Ready(Ok(()));
         }
 
@@ -79,7 +126,7 @@ impl Future for SleepFuture
         let old_waker = self.waker.lock().unwrap().replace(cx.waker().clone());
         if old_waker.is_none()
         {
-            schedule_wake(self.deadline, self.waker.clone())?;
+            self.key = schedule_wake(self.deadline, self.waker.clone())?;
         }
 
         Poll::Pending
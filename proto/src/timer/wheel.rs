@@ -0,0 +1,388 @@
+/*
+
+    Hierarchical timing wheel.
+
+    Time is counted in ticks since the start of the timer. The wheel has
+    `LEVELS` levels of `SLOTS` slots: a slot of level 0 covers one tick, and a
+    slot of level `n` covers `SLOTS` slots of level `n - 1` . An entry goes to
+    the level of the highest bit in which its tick differs from the elapsed
+    tick. Each entry knows its position in its slot, so inserting and removing
+    an entry do not depend on the number of entries.
+
+    When the wheel reaches a slot of level 0, all the entries of the slot
+    expire together. When it reaches a slot of a higher level, the entries of
+    the slot move down to the lower levels.
+
+*/
+
+use core::mem;
+
+
+//------------------------------------------------------------------------------
+//  Shape of the wheel. With 1 millisecond ticks, the levels cover about 2
+//  years. Entries further away go to the last level and move down when it is
+//  reached.
+//------------------------------------------------------------------------------
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const LEVELS: usize = 6;
+const MAX_TICKS: u64 = 1 << (SLOT_BITS * LEVELS as u32);
+
+
+//------------------------------------------------------------------------------
+//  Entry of the wheel.
+//------------------------------------------------------------------------------
+struct Entry<T>
+{
+    when: u64,
+    id: u64,
+    pos: usize,
+    level: usize,
+    slot: usize,
+    value: T,
+}
+
+
+//------------------------------------------------------------------------------
+//  Key of an entry, to remove it before it expires. `id` tells apart the
+//  entries that reuse the same index of the slab.
+//------------------------------------------------------------------------------
+#[derive(Debug)]
+pub(crate) struct WheelKey
+{
+    index: usize,
+    id: u64,
+}
+
+
+//------------------------------------------------------------------------------
+//  Level of the wheel. Bit `n` of `occupied` is set if slot `n` has entries.
+//------------------------------------------------------------------------------
+struct Level
+{
+    occupied: u64,
+    slots: Vec<Vec<usize>>,
+}
+
+impl Level
+{
+    fn new() -> Self
+    {
+        Self
+        {
+            occupied: 0,
+            slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+        }
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Timing wheel. The entries are stored in a slab, and the slots hold their
+//  indices.
+//------------------------------------------------------------------------------
+pub(crate) struct Wheel<T>
+{
+    elapsed: u64,
+    levels: Vec<Level>,
+    entries: Vec<Option<Entry<T>>>,
+    free: Vec<usize>,
+    next_id: u64,
+    len: usize,
+}
+
+impl<T> Wheel<T>
+{
+    //--------------------------------------------------------------------------
+    //  Creates an empty wheel at tick 0.
+    //--------------------------------------------------------------------------
+    pub(crate) fn new() -> Self
+    {
+        Self
+        {
+            elapsed: 0,
+            levels: (0..LEVELS).map(|_| Level::new()).collect(),
+            entries: Vec::new(),
+            free: Vec::new(),
+            next_id: 0,
+            len: 0,
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the number of entries that have not expired.
+    //--------------------------------------------------------------------------
+    pub(crate) fn len( &self ) -> usize
+    {
+        self.len
+    }
+
+    //--------------------------------------------------------------------------
+    //  Adds `value` to expire at tick `when` . Returns `value` back if `when`
+    //  has already elapsed.
+    //--------------------------------------------------------------------------
+    pub(crate) fn insert
+    (
+        &mut self,
+        when: u64,
+        value: T,
+    ) -> Result<WheelKey, T>
+    {
+        if when <= self.elapsed
+        {
+            return Err(value);
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        let entry = Some(Entry { when, id, pos: 0, level: 0, slot: 0, value });
+        let index = match self.free.pop()
+        {
+            Some(index) =>
+            {
+                self.entries[index] = entry;
+                index
+            },
+            None =>
+            {
+                self.entries.push(entry);
+                self.entries.len() - 1
+            },
+        };
+        self.link(index, when);
+        self.len += 1;
+        Ok(WheelKey { index, id })
+    }
+
+    //--------------------------------------------------------------------------
+    //  Removes the entry of `key` . Returns its value, or `None` if it has
+    //  already expired or been removed.
+    //--------------------------------------------------------------------------
+    pub(crate) fn remove( &mut self, key: &WheelKey ) -> Option<T>
+    {
+        let entry = self.entries.get(key.index)?.as_ref()?;
+        if entry.id != key.id
+        {
+            return None;
+        }
+        let (level, slot, pos) = (entry.level, entry.slot, entry.pos);
+
+        //  Moves the last index of the slot to the position of the entry.
+        let indices = &mut self.levels[level].slots[slot];
+        indices.swap_remove(pos);
+        if let Some(&moved) = indices.get(pos)
+        {
+            self.entries[moved].as_mut().unwrap().pos = pos;
+        }
+        if indices.is_empty()
+        {
+            self.levels[level].occupied &= !(1 << slot);
+        }
+
+        self.free.push(key.index);
+        self.len -= 1;
+        self.entries[key.index].take().map(|entry| entry.value)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the tick at which the wheel must be advanced next, if it has
+    //  entries. No entry expires before this tick.
+    //--------------------------------------------------------------------------
+    pub(crate) fn next_expiration( &self ) -> Option<u64>
+    {
+        self.next_slot().map(|(_, _, deadline)| deadline)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Advances the wheel to tick `now` and moves the values of the expired
+    //  entries to `expired` .
+    //--------------------------------------------------------------------------
+    pub(crate) fn advance( &mut self, now: u64, expired: &mut Vec<T> )
+    {
+        while let Some((level, slot, deadline)) = self.next_slot()
+        {
+            if deadline > now
+            {
+                break;
+            }
+            self.elapsed = deadline;
+
+            let indices = mem::take(&mut self.levels[level].slots[slot]);
+            self.levels[level].occupied &= !(1 << slot);
+            for index in indices
+            {
+                let when = self.entries[index].as_ref().unwrap().when;
+                if when <= self.elapsed
+                {
+                    let entry = self.entries[index].take().unwrap();
+                    self.free.push(index);
+                    self.len -= 1;
+                    expired.push(entry.value);
+                }
+                else
+                {
+                    self.link(index, when);
+                }
+            }
+        }
+        self.elapsed = self.elapsed.max(now);
+    }
+
+    //--------------------------------------------------------------------------
+    //  Puts the entry at `index` in the slot for tick `when` .
+    //--------------------------------------------------------------------------
+    fn link( &mut self, index: usize, when: u64 )
+    {
+        let level = level_for(self.elapsed, when);
+        let slot = slot_for(level, when);
+        let indices = &mut self.levels[level].slots[slot];
+        let entry = self.entries[index].as_mut().unwrap();
+        entry.pos = indices.len();
+        entry.level = level;
+        entry.slot = slot;
+        indices.push(index);
+        self.levels[level].occupied |= 1 << slot;
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the level, the slot and the starting tick of the next slot that
+    //  has entries. The lowest level with entries has the earliest slot.
+    //--------------------------------------------------------------------------
+    fn next_slot( &self ) -> Option<(usize, usize, u64)>
+    {
+        self.levels.iter().enumerate().find_map(|(level, entries)|
+        {
+            if entries.occupied == 0
+            {
+                return None;
+            }
+            let slot_range = slot_range(level);
+            let level_range = slot_range * SLOTS as u64;
+            let now_slot = self.elapsed / slot_range;
+            let zeros = entries.occupied.rotate_right(now_slot as u32)
+                .trailing_zeros();
+            let slot = (zeros as usize + now_slot as usize) % SLOTS;
+
+            //  Only a slot of the last level, holding entries beyond the range
+            //  of the wheel, can be behind the elapsed tick.
+            let level_start = self.elapsed & !(level_range - 1);
+            let mut deadline = level_start + slot as u64 * slot_range;
+            if deadline <= self.elapsed
+            {
+                deadline += level_range;
+            }
+            Some((level, slot, deadline))
+        })
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Returns the number of ticks covered by a slot of `level` .
+//------------------------------------------------------------------------------
+fn slot_range( level: usize ) -> u64
+{
+    1 << (SLOT_BITS * level as u32)
+}
+
+
+//------------------------------------------------------------------------------
+//  Returns the level for tick `when` , from the highest bit in which it differs
+//  from `elapsed` .
+//------------------------------------------------------------------------------
+fn level_for( elapsed: u64, when: u64 ) -> usize
+{
+    let masked = ((elapsed ^ when) | (SLOTS as u64 - 1)).min(MAX_TICKS - 1);
+    let significant = 63 - masked.leading_zeros();
+    (significant / SLOT_BITS) as usize
+}
+
+
+//------------------------------------------------------------------------------
+//  Returns the slot of `level` for tick `when` .
+//------------------------------------------------------------------------------
+fn slot_for( level: usize, when: u64 ) -> usize
+{
+    ((when >> (SLOT_BITS * level as u32)) as usize) % SLOTS
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::Wheel;
+
+    #[test]
+    fn wheel_expires_in_order()
+    {
+        let mut wheel = Wheel::new();
+        for when in [5, 1, 70, 64, 5000, 3]
+        {
+            wheel.insert(when, when).unwrap();
+        }
+        assert_eq!(wheel.insert(0, 0).unwrap_err(), 0);
+
+        let mut expired = Vec::new();
+        let mut order = Vec::new();
+        while let Some(next) = wheel.next_expiration()
+        {
+            wheel.advance(next, &mut expired);
+            for when in expired.drain(..)
+            {
+                assert!(when <= next);
+                order.push(when);
+            }
+        }
+        assert_eq!(order, [1, 3, 5, 64, 70, 5000]);
+    }
+
+    #[test]
+    fn wheel_advance_in_batch()
+    {
+        let mut wheel = Wheel::new();
+        for when in 1..=1000
+        {
+            wheel.insert(when, when).unwrap();
+        }
+        wheel.insert(1 << 40, 0).unwrap();
+
+        let mut expired = Vec::new();
+        wheel.advance(500, &mut expired);
+        expired.sort_unstable();
+        assert_eq!(expired, (1..=500).collect::<Vec<_>>());
+        assert_eq!(wheel.insert(500, 500).unwrap_err(), 500);
+
+        expired.clear();
+        wheel.advance(1 << 40, &mut expired);
+        assert_eq!(expired.len(), 501);
+        assert_eq!(wheel.next_expiration(), None);
+    }
+
+    #[test]
+    fn wheel_remove()
+    {
+        let mut wheel = Wheel::new();
+        let keys: Vec<_> = (1..=10)
+            .map(|when| wheel.insert(when, when).unwrap())
+            .collect();
+        let far = wheel.insert(100_000, 0).unwrap();
+        assert_eq!(wheel.len(), 11);
+
+        assert_eq!(wheel.remove(&keys[4]), Some(5));
+        assert_eq!(wheel.remove(&keys[4]), None);
+        assert_eq!(wheel.remove(&far), Some(0));
+        assert_eq!(wheel.len(), 9);
+
+        //  The index of a removed entry is reused with another id.
+        let reused = wheel.insert(20, 20).unwrap();
+        assert_eq!(wheel.remove(&far), None);
+
+        let mut expired = Vec::new();
+        wheel.advance(10, &mut expired);
+        expired.sort_unstable();
+        assert_eq!(expired, [1, 2, 3, 4, 6, 7, 8, 9, 10]);
+        assert_eq!(wheel.remove(&keys[0]), None);
+        assert_eq!(wheel.remove(&reused), Some(20));
+        assert_eq!(wheel.len(), 0);
+        assert_eq!(wheel.next_expiration(), None);
+    }
+}
@@ -6,81 +6,84 @@
 
 mod task;
 
-use crate::threadpool::{ ThreadPool, Task as ThreadPoolTask };
-use task::Task;
+use crate::threadpool::ThreadPool;
+use task::{ Task, TaskSender };
 
-use std::task::Poll;
+use std::task::{ Context, Poll, Wake, Waker };
 use std::marker::Unpin;
 use std::pin::Pin;
 use std::future::Future;
-use std::sync::{ Mutex, Arc };
-use std::sync::mpsc::{ self, SyncSender, Sender, Receiver };
+use std::sync::Arc;
+use std::thread::Thread;
 
 
 pub struct Executor
 {
-    pool: ThreadPool,
-    task_sender: Sender<Task>,
-    task_queue: Receiver<Task>,
+    pool: Arc<ThreadPool>,
 }
 
 impl Executor
 {
     //--------------------------------------------------------------------------
     //  Creates an executor.
+    //
+    //  Panics if the worker threads cannot be started.
     //--------------------------------------------------------------------------
     pub fn new() -> Self
     {
-        let (sender, receiver) = mpsc::channel();
+        let pool = ThreadPool::new("wexing", 4);
+        pool.run().expect("failed to start the executor threads");
         Self
         {
-            pool: ThreadPool::new("wexing", 4),
-            task_sender: sender,
-            task_queue: receiver,
+            pool: Arc::new(pool),
         }
     }
 
     //--------------------------------------------------------------------------
     //  Block on
+    //
+    //  Polls `fut` on the current thread, parking it until `fut` is woken, and
+    //  returns its output. Tasks spawned by `fut` run on the workers.
     //--------------------------------------------------------------------------
-    pub fn block_on<T>( &self, fut: impl Future<Output = T> + Send + 'static )
+    pub fn block_on<T>( &self, fut: impl Future<Output = T> ) -> T
     {
-        self.block_on_unpin(Box::pin(fut));
+        self.block_on_unpin(Box::pin(fut))
     }
 
     pub fn block_on_unpin<T>
     (
         &self,
-        mut fut: impl Future<Output = T> + Send + Unpin + 'static
+        mut fut: impl Future<Output = T> + Unpin
     ) -> T
     {
-        struct BlockOnTaskWaker(Mutex<Option<SyncSender<()>>>);
-        impl std::task::Wake for BlockOnTaskWaker
+        struct BlockOnWaker(Thread);
+        impl Wake for BlockOnWaker
         {
             fn wake( self: Arc<Self> )
             {
-                if let Some(sender) = self.0.lock().unwrap().take()
-                {
-                    let _ = sender.send(());
-                }
+                self.0.unpark();
+            }
+
+            fn wake_by_ref( self: &Arc<Self> )
+            {
+                self.0.unpark();
             }
         }
 
+        let waker = Waker::from(Arc::new
+        (
+            BlockOnWaker(std::thread::current())
+        ));
+        let mut cx = Context::from_waker(&waker);
         loop
         {
-            let (sender, receiver) = mpsc::sync_channel(1);
-            let waker = std::task::Waker::from(Arc::new
-            (
-                BlockOnTaskWaker(Mutex::new(Some(sender)))
-            ));
-            let mut cx = std::task::Context::from_waker(&waker);
-            let task = ThreadPoolTask::new(Box::new(|| {Pin::new(&mut fut).poll(&mut cx);}), 0);
-            self.pool.schedule(task);
-//            if let Poll::Ready(result) = Pin::new(&mut fut).poll(&mut cx)
-//            {
-//                return result;
-//            }
-            receiver.recv().unwrap();
+            if let Poll::Ready(result) = Pin::new(&mut fut).poll(&mut cx)
+            {
+                return result;
+            }
+
+            //  A wakeup before `park` makes it return immediately.
+            std::thread::park();
         }
     }
 
@@ -89,7 +92,8 @@ impl Executor
     //--------------------------------------------------------------------------
     pub fn spawn( &self, fut: impl Future<Output = ()> + Send + 'static )
     {
-        self.spawn_unpin(Box::pin(fut));
+        let task = Task::new(fut, TaskSender::new(&self.pool));
+        task.schedule();
     }
 
     pub fn spawn_unpin
@@ -98,35 +102,15 @@ impl Executor
         fut: impl Future<Output = ()> + Send + Unpin + 'static
     )
     {
-        let task = Task::new
-        (
-            Pin::new(Box::new(fut)), self.task_sender.clone(),
-        );
-        self.schedule(task);
-    }
-
-    //--------------------------------------------------------------------------
-    //  Schedules a task.
-    //--------------------------------------------------------------------------
-    pub fn schedule( &self, task: Task )
-    {
-        self.task_sender.send(task).unwrap();
+        self.spawn(fut);
     }
+}
 
-    //--------------------------------------------------------------------------
-    //  Executes receive task.
-    //--------------------------------------------------------------------------
-    pub fn run( self )
+impl Default for Executor
+{
+    fn default() -> Self
     {
-        loop
-        {
-            let mut task = self.task_queue.recv().unwrap();
-            match task.poll()
-            {
-                Poll::Pending => { self.schedule(task) },
-                Poll::Ready(()) => {},
-            }
-        }
+        Self::new()
     }
 }
 
@@ -236,13 +220,81 @@ mod test
         }
 
         let executor = Executor::new();
-        executor.spawn(async
+        let (sender, receiver) = std::sync::mpsc::channel();
+        executor.spawn(async move
         {
             TestFuture{ state: 0 }.await;
             TestFuture{ state: 0 }.await;
             TestFuture{ state: 0 }.await;
             TestFuture{ state: 0 }.await;
+            sender.send(()).unwrap();
         });
-        executor.run();
+        receiver
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+    }
+
+    #[test]
+    fn block_on_output()
+    {
+        let executor = Executor::new();
+        assert_eq!(executor.block_on(async { 40 + 2 }), 42);
+    }
+
+    #[test]
+    fn wake_from_other_thread()
+    {
+        use std::sync::{ Arc, Mutex };
+
+        //  Completes when another thread sets `done` and wakes it.
+        struct Shared
+        {
+            done: bool,
+            waker: Option<std::task::Waker>,
+        }
+        struct WaitFuture(Arc<Mutex<Shared>>);
+        impl Future for WaitFuture
+        {
+            type Output = &'static str;
+
+            fn poll( self: Pin<&mut Self>, cx: &mut Context<'_> )
+                -> Poll<Self::Output>
+            {
+                let mut shared = self.0.lock().unwrap();
+                if shared.done
+                {
+                    return Poll::Ready("done");
+                }
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+
+        let shared = Arc::new(Mutex::new(Shared { done: false, waker: None }));
+        let shared_clone = shared.clone();
+        std::thread::spawn(move ||
+        {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            let mut shared = shared_clone.lock().unwrap();
+            shared.done = true;
+            if let Some(waker) = shared.waker.take()
+            {
+                waker.wake();
+            }
+        });
+
+        let executor = Executor::new();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let shared_clone = shared.clone();
+        executor.spawn(async move
+        {
+            sender.send(WaitFuture(shared_clone).await).unwrap();
+        });
+        assert_eq!
+        (
+            receiver.recv_timeout(std::time::Duration::from_secs(5)),
+            Ok("done")
+        );
+        assert_eq!(executor.block_on(WaitFuture(shared)), "done");
     }
 }
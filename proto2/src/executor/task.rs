@@ -1,13 +1,60 @@
+use crate::threadpool::{ ThreadPool, Task as ThreadPoolTask };
+
 use std::pin::Pin;
-use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
-use std::marker::Unpin;
+use std::task::{ Context, Poll, Wake, Waker };
 use std::future::Future;
-use std::sync::mpsc::Sender;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::{ Arc, Mutex, Weak };
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+
+//------------------------------------------------------------------------------
+//  Sends tasks to the workers of a threadpool. Holds a weak reference, since
+//  the queues of the threadpool hold the tasks.
+//------------------------------------------------------------------------------
+#[derive(Clone)]
+pub(crate) struct TaskSender
+{
+    pool: Weak<ThreadPool>,
+}
+
+impl TaskSender
+{
+    //--------------------------------------------------------------------------
+    //  Creates a task sender.
+    //--------------------------------------------------------------------------
+    pub fn new( pool: &Arc<ThreadPool> ) -> Self
+    {
+        Self
+        {
+            pool: Arc::downgrade(pool),
+        }
+    }
 
+    //--------------------------------------------------------------------------
+    //  Schedules `task` to be polled by a worker. Does nothing if the
+    //  threadpool has been dropped.
+    //--------------------------------------------------------------------------
+    pub fn send( &self, task: Arc<Task> )
+    {
+        if let Some(pool) = self.pool.upgrade()
+        {
+            pool.schedule(ThreadPoolTask::new(Box::new(move || task.run()), 0));
+        }
+    }
+}
+
+
+//------------------------------------------------------------------------------
+//  Spawned future. The task is its own waker: waking it sends it to the
+//  threadpool through `task_sender` .
+//------------------------------------------------------------------------------
 pub struct Task
 {
-    future: Pin<Box<dyn Future<Output = ()> + Send + Unpin + 'static>>,
-    task_sender: Sender<Task>,
+    future: Mutex<Option<BoxFuture>>,
+    scheduled: AtomicBool,
+    task_sender: TaskSender,
 }
 
 impl Task
@@ -15,43 +62,60 @@ impl Task
     //--------------------------------------------------------------------------
     //  Creates a task.
     //--------------------------------------------------------------------------
-    pub fn new
+    pub(crate) fn new
     (
-        fut: Pin<Box<impl Future<Output = ()> + Send + Unpin + 'static>>,
-        task_sender: Sender<Task>,
-    ) -> Self
+        fut: impl Future<Output = ()> + Send + 'static,
+        task_sender: TaskSender,
+    ) -> Arc<Self>
     {
-        Self
+        Arc::new(Self
         {
-            future: fut,
+            future: Mutex::new(Some(Box::pin(fut))),
+            scheduled: AtomicBool::new(false),
             task_sender,
+        })
+    }
+
+    //--------------------------------------------------------------------------
+    //  Sends the task to the threadpool, unless it is already queued.
+    //--------------------------------------------------------------------------
+    pub(crate) fn schedule( self: &Arc<Self> )
+    {
+        if !self.scheduled.swap(true, Ordering::AcqRel)
+        {
+            self.task_sender.send(self.clone());
         }
     }
 
     //--------------------------------------------------------------------------
-    //  Polls task.
+    //  Polls task. Called by a worker of the threadpool.
     //--------------------------------------------------------------------------
-    pub fn poll( &mut self ) -> Poll<()>
+    fn run( self: Arc<Self> )
     {
-        let waker = dummy_waker();
+        //  Cleared before polling, so that a wakeup during the poll queues the
+        //  task again.
+        self.scheduled.store(false, Ordering::Release);
+
+        let mut guard = self.future.lock().unwrap();
+        let Some(future) = guard.as_mut() else { return };
+        let waker = Waker::from(self.clone());
         let mut cx = Context::from_waker(&waker);
-        Future::poll(self.future.as_mut(), &mut cx)
+        if let Poll::Ready(()) = future.as_mut().poll(&mut cx)
+        {
+            *guard = None;
+        }
     }
 }
 
-fn dummy_raw_waker() -> RawWaker
+impl Wake for Task
 {
-    fn no_op(_: *const ()) {}
-    fn clone(_: *const ()) -> RawWaker
+    fn wake( self: Arc<Self> )
     {
-        dummy_raw_waker()
+        self.schedule();
     }
 
-    let vtable = &RawWakerVTable::new(clone, no_op, no_op, no_op);
-    RawWaker::new(std::ptr::null::<()>(), vtable)
-}
-
-fn dummy_waker() -> Waker
-{
-    unsafe { Waker::from_raw(dummy_raw_waker()) }
+    fn wake_by_ref( self: &Arc<Self> )
+    {
+        self.schedule();
+    }
 }
@@ -1,8 +1,15 @@
-use super::TaskQueue;
+use super::{ Task, TaskQueue };
 
+use std::sync::Mutex;
+
+
+//------------------------------------------------------------------------------
+//  Injection queue shared by all the workers. Receives the tasks scheduled
+//  from outside the workers and the overflow of the local queues.
+//------------------------------------------------------------------------------
 pub(crate) struct GlobalTaskQueue
 {
-    task_queue: TaskQueue,
+    task_queue: Mutex<TaskQueue>,
 }
 
 impl GlobalTaskQueue
@@ -14,7 +21,39 @@ impl GlobalTaskQueue
     {
         Self
         {
-            task_queue: TaskQueue::new(),
+            task_queue: Mutex::new(TaskQueue::new()),
         }
     }
+
+    //--------------------------------------------------------------------------
+    //  Adds a task.
+    //--------------------------------------------------------------------------
+    pub fn push( &self, task: Task )
+    {
+        self.task_queue.lock().unwrap().push(task);
+    }
+
+    //--------------------------------------------------------------------------
+    //  Adds tasks, in order.
+    //--------------------------------------------------------------------------
+    pub fn push_batch( &self, tasks: impl IntoIterator<Item = Task> )
+    {
+        self.task_queue.lock().unwrap().push_batch(tasks);
+    }
+
+    //--------------------------------------------------------------------------
+    //  Removes the oldest task.
+    //--------------------------------------------------------------------------
+    pub fn pop( &self ) -> Option<Task>
+    {
+        self.task_queue.lock().unwrap().pop()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns true if the queue is empty.
+    //--------------------------------------------------------------------------
+    pub fn is_empty( &self ) -> bool
+    {
+        self.task_queue.lock().unwrap().is_empty()
+    }
 }
@@ -1,8 +1,23 @@
-use super::{ Task, GlobalTaskQueue, Worker, Stealer };
+use super::{ Task, GlobalTaskQueue, LocalTaskQueue, Worker, Stealer };
+use super::worker;
 use crate::util::AtomicCounter;
 
-use std::sync::mpsc::{ self, Receiver, Sender };
-use std::sync::{ Arc, Mutex };
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::sync::{ Arc, Condvar, Mutex };
+
+
+//------------------------------------------------------------------------------
+//  Parking state of the workers.
+//
+//  `notifications` counts the tasks scheduled since the last time an idle
+//  worker checked, so that a task scheduled between the last search of a
+//  worker and its parking wakes it immediately.
+//------------------------------------------------------------------------------
+struct SleepState
+{
+    notifications: usize,
+    sleeping: usize,
+}
 
 pub(crate) struct Inner
 {
@@ -10,9 +25,11 @@ pub(crate) struct Inner
     name_thread_cnt: AtomicCounter,
     size: usize,
     global_queue: GlobalTaskQueue,
-    stealer: Stealer,
-    receiver: Arc<Mutex<Receiver<Task>>>,
-    sender: Sender<Task>,
+    stealers: Vec<Stealer>,
+    sleep_state: Mutex<SleepState>,
+    sleep_condvar: Condvar,
+    live_threads: AtomicUsize,
+    is_shutdown: AtomicBool,
 }
 
 impl Inner
@@ -22,23 +39,30 @@ impl Inner
     //--------------------------------------------------------------------------
     pub fn new( name: &'static str, size: usize ) -> Self
     {
-        let (sender, receiver) = mpsc::channel::<Task>();
         Self
         {
             name,
             name_thread_cnt: AtomicCounter::new(),
             size,
             global_queue: GlobalTaskQueue::new(),
-            stealer: Stealer::new(),
-            receiver: Arc::new(Mutex::new(receiver)),
-            sender,
+            stealers: (0..size)
+                .map(|_| Stealer::new(Arc::new(LocalTaskQueue::new())))
+                .collect(),
+            sleep_state: Mutex::new(SleepState
+            {
+                notifications: 0,
+                sleeping: 0,
+            }),
+            sleep_condvar: Condvar::new(),
+            live_threads: AtomicUsize::new(0),
+            is_shutdown: AtomicBool::new(false),
         }
     }
 
     //--------------------------------------------------------------------------
     //  Starts threads.
     //--------------------------------------------------------------------------
-    pub fn start_threads( &self ) -> Result<(), std::io::Error>
+    pub fn start_threads( self: &Arc<Self> ) -> Result<(), std::io::Error>
     {
         while self.size > self.num_live_threads()
         {
@@ -49,23 +73,23 @@ impl Inner
     }
 
     //--------------------------------------------------------------------------
-    //  Start a worker thread.
+    //  Start a worker thread. The n-th thread owns the local queue n modulo
+    //  the size of the pool.
     //--------------------------------------------------------------------------
-    pub fn start_thread( &self ) -> Result<(), std::io::Error>
+    pub fn start_thread( self: &Arc<Self> ) -> Result<(), std::io::Error>
     {
-        let receiver_clone = self.receiver.clone();
-        let worker = Arc::new(Worker::new(receiver_clone));
-        let thread_name = format!
-        (
-            "{}-{}",
-            self.name,
-            self.name_thread_cnt.next()
-        );
-
-        let worker_clone = worker.clone();
+        let num = self.name_thread_cnt.next();
+        let worker = Worker::new(self.clone(), num % self.size);
+        let thread_name = format!("{}-{}", self.name, num);
+
+        self.live_threads.fetch_add(1, Ordering::AcqRel);
         std::thread::Builder::new()
             .name(thread_name)
-            .spawn(move || worker_clone.work())?;
+            .spawn(move || worker.work())
+            .inspect_err(|_|
+            {
+                self.live_threads.fetch_sub(1, Ordering::AcqRel);
+            })?;
 
         Ok(())
     }
@@ -75,15 +99,101 @@ impl Inner
     //--------------------------------------------------------------------------
     pub fn num_live_threads( &self ) -> usize
     {
-        Arc::strong_count(&self.receiver)
+        self.live_threads.load(Ordering::Acquire)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Records that a worker thread stopped.
+    //--------------------------------------------------------------------------
+    pub(crate) fn thread_stopped( &self )
+    {
+        self.live_threads.fetch_sub(1, Ordering::AcqRel);
     }
 
     //--------------------------------------------------------------------------
     //  Schedules a task.
+    //
+    //  From a worker thread of this pool, the task goes to the local queue of
+    //  the worker. Otherwise, it goes to the global queue.
     //--------------------------------------------------------------------------
     pub fn schedule( &self, task: Task )
     {
-        let sender_clone = self.sender.clone();
-        sender_clone.send(task).unwrap();
+        match worker::current_local_queue(self)
+        {
+            Some(local_queue) => local_queue.push(task, &self.global_queue),
+            None => self.global_queue.push(task),
+        }
+        self.notify_one();
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the global queue.
+    //--------------------------------------------------------------------------
+    pub(crate) fn global_queue( &self ) -> &GlobalTaskQueue
+    {
+        &self.global_queue
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the stealers of the local queues, indexed by worker.
+    //--------------------------------------------------------------------------
+    pub(crate) fn stealers( &self ) -> &[Stealer]
+    {
+        &self.stealers
+    }
+
+    //--------------------------------------------------------------------------
+    //  Wakes a parked worker, or makes the next worker about to park search
+    //  again.
+    //--------------------------------------------------------------------------
+    fn notify_one( &self )
+    {
+        let mut state = self.sleep_state.lock().unwrap();
+        if state.notifications < self.size
+        {
+            state.notifications += 1;
+        }
+        if state.sleeping > 0
+        {
+            self.sleep_condvar.notify_one();
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Parks the current worker until a task is scheduled or the pool shuts
+    //  down.
+    //--------------------------------------------------------------------------
+    pub(crate) fn park( &self )
+    {
+        let mut state = self.sleep_state.lock().unwrap();
+        while state.notifications == 0 && !self.is_shutdown()
+        {
+            state.sleeping += 1;
+            state = self.sleep_condvar.wait(state).unwrap();
+            state.sleeping -= 1;
+        }
+        if state.notifications > 0
+        {
+            state.notifications -= 1;
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Stops the workers after their current task. Tasks still queued are
+    //  dropped with the pool.
+    //--------------------------------------------------------------------------
+    pub(crate) fn shutdown( &self )
+    {
+        let _state = self.sleep_state.lock().unwrap();
+        self.is_shutdown.store(true, Ordering::Release);
+        self.sleep_condvar.notify_all();
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns true after `shutdown` .
+    //--------------------------------------------------------------------------
+    pub(crate) fn is_shutdown( &self ) -> bool
+    {
+        self.is_shutdown.load(Ordering::Acquire)
     }
 }
@@ -1,8 +1,22 @@
-use super::TaskQueue;
+use super::{ GlobalTaskQueue, Task, TaskQueue };
 
+use std::sync::Mutex;
+
+
+//------------------------------------------------------------------------------
+//  Maximum number of tasks in a local queue. When it is full, half of the
+//  tasks move to the global queue.
+//------------------------------------------------------------------------------
+pub(crate) const LOCAL_QUEUE_CAPACITY: usize = 256;
+
+
+//------------------------------------------------------------------------------
+//  Bounded queue of a worker. The worker pushes and pops its own tasks; the
+//  other workers steal from it through a `Stealer` .
+//------------------------------------------------------------------------------
 pub(crate) struct LocalTaskQueue
 {
-    task_queue: TaskQueue,
+    task_queue: Mutex<TaskQueue>,
 }
 
 impl LocalTaskQueue
@@ -14,7 +28,58 @@ impl LocalTaskQueue
     {
         Self
         {
-            task_queue: TaskQueue::new(),
+            task_queue: Mutex::new(TaskQueue::new()),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Adds a task. If the queue is full, moves the task and the newest half
+    //  of the queue to `global_queue` .
+    //--------------------------------------------------------------------------
+    pub fn push( &self, task: Task, global_queue: &GlobalTaskQueue )
+    {
+        let mut task_queue = self.task_queue.lock().unwrap();
+        if task_queue.len() < LOCAL_QUEUE_CAPACITY
+        {
+            task_queue.push(task);
+            return;
         }
+
+        let mut overflow = task_queue.take_half();
+        drop(task_queue);
+        overflow.push(task);
+        global_queue.push_batch(overflow);
+    }
+
+    //--------------------------------------------------------------------------
+    //  Adds tasks stolen from another queue. The caller ensures that they fit.
+    //--------------------------------------------------------------------------
+    pub fn push_batch( &self, tasks: Vec<Task> )
+    {
+        self.task_queue.lock().unwrap().push_batch(tasks);
+    }
+
+    //--------------------------------------------------------------------------
+    //  Removes the oldest task.
+    //--------------------------------------------------------------------------
+    pub fn pop( &self ) -> Option<Task>
+    {
+        self.task_queue.lock().unwrap().pop()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Removes the newest half of the tasks, for a stealer.
+    //--------------------------------------------------------------------------
+    pub fn take_half( &self ) -> Vec<Task>
+    {
+        self.task_queue.lock().unwrap().take_half()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns true if the queue is empty.
+    //--------------------------------------------------------------------------
+    pub fn is_empty( &self ) -> bool
+    {
+        self.task_queue.lock().unwrap().is_empty()
     }
 }
@@ -2,6 +2,12 @@
 
     ThreadPool
 
+    Work-stealing scheduler. Each worker owns a bounded local queue. Tasks
+    scheduled from a worker go to its local queue, and overflow to the global
+    queue when it is full; tasks scheduled from other threads go to the global
+    queue. A worker without tasks steals half of the local queue of a random
+    worker, and parks when there is nothing to steal.
+
 */
 
 mod inner;
@@ -26,9 +32,11 @@ pub(crate) use stealer::Stealer;
 
 use inner::Inner;
 
+use std::sync::Arc;
+
 pub struct ThreadPool
 {
-    inner: Inner,
+    inner: Arc<Inner>,
 }
 
 impl ThreadPool
@@ -40,7 +48,7 @@ impl ThreadPool
     {
         Self
         {
-            inner: Inner::new(name, size),
+            inner: Arc::new(Inner::new(name, size)),
         }
     }
 
@@ -69,10 +77,23 @@ impl ThreadPool
     }
 }
 
+impl Drop for ThreadPool
+{
+    //--------------------------------------------------------------------------
+    //  Stops the workers after their current task.
+    //--------------------------------------------------------------------------
+    fn drop( &mut self )
+    {
+        self.inner.shutdown();
+    }
+}
+
 #[cfg(test)]
 mod test
 {
     use super::{ Task, ThreadPool };
+    use std::sync::{ mpsc, Arc };
+    use std::time::Duration;
 
     #[test]
     fn threadpool()
@@ -81,7 +102,62 @@ mod test
         tp.run().unwrap();
         assert_eq!(tp.num_live_threads(), 10);
 
-        let task = Task::new(Box::new(|| { println!("do task"); }), 0);
+        let (sender, receiver) = mpsc::channel();
+        let task = Task::new
+        (
+            Box::new(move || { sender.send(()).unwrap(); }),
+            0,
+        );
         tp.schedule(task);
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn threadpool_schedule_from_worker()
+    {
+        //  Fills the local queue of one worker past its capacity, so that the
+        //  tasks overflow to the global queue and are stolen by the others.
+        let tp = Arc::new(ThreadPool::new("wexing", 4));
+        tp.run().unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let tp_clone = tp.clone();
+        tp.schedule(Task::new(Box::new(move ||
+        {
+            for num in 0..1000
+            {
+                let sender = sender.clone();
+                tp_clone.schedule(Task::new(Box::new(move ||
+                {
+                    sender.send(num).unwrap();
+                }), 0));
+            }
+        }), 0));
+
+        let mut values: Vec<usize> = (0..1000)
+            .map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        values.sort_unstable();
+        assert_eq!((0..1000).collect::<Vec<usize>>(), values);
+    }
+
+    #[test]
+    fn threadpool_shutdown()
+    {
+        let tp = ThreadPool::new("wexing", 2);
+        tp.run().unwrap();
+        let inner = tp.inner.clone();
+        drop(tp);
+
+        //  Parked workers wake up and stop.
+        for _ in 0..500
+        {
+            if inner.num_live_threads() == 0
+            {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("workers did not stop");
     }
 }
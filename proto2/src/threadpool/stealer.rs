@@ -1,12 +1,45 @@
-pub(crate) struct Stealer {}
+use super::{ LocalTaskQueue, Task };
+
+use std::sync::Arc;
+
+
+//------------------------------------------------------------------------------
+//  Handle to steal tasks from the local queue of a worker.
+//------------------------------------------------------------------------------
+pub(crate) struct Stealer
+{
+    queue: Arc<LocalTaskQueue>,
+}
 
 impl Stealer
 {
     //--------------------------------------------------------------------------
     //  Creates a stealer
     //--------------------------------------------------------------------------
-    pub fn new() -> Self
+    pub fn new( queue: Arc<LocalTaskQueue> ) -> Self
+    {
+        Self { queue }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Moves half of the tasks of the victim queue to `dest` . Returns one of
+    //  them to run immediately, or `None` if the victim queue was empty.
+    //--------------------------------------------------------------------------
+    pub fn steal_into( &self, dest: &LocalTaskQueue ) -> Option<Task>
+    {
+        //  The victim lock is released before `dest` is locked, so two
+        //  workers stealing from each other cannot deadlock.
+        let mut stolen = self.queue.take_half().into_iter();
+        let first = stolen.next()?;
+        dest.push_batch(stolen.collect());
+        Some(first)
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the queue this stealer steals from.
+    //--------------------------------------------------------------------------
+    pub fn queue( &self ) -> &Arc<LocalTaskQueue>
     {
-        Self {}
+        &self.queue
     }
 }
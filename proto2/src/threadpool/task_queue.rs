@@ -1,8 +1,15 @@
 use super::Task;
 
+use std::collections::VecDeque;
+
+
+//------------------------------------------------------------------------------
+//  FIFO queue of tasks. Not synchronized; `GlobalTaskQueue` and
+//  `LocalTaskQueue` wrap it in a lock.
+//------------------------------------------------------------------------------
 pub(crate) struct TaskQueue
 {
-    queue: Vec<Task>,
+    queue: VecDeque<Task>,
 }
 
 impl TaskQueue
@@ -14,7 +21,56 @@ impl TaskQueue
     {
         Self
         {
-            queue: Vec::new(),
+            queue: VecDeque::new(),
         }
     }
+
+    //--------------------------------------------------------------------------
+    //  Adds a task at the back of the queue.
+    //--------------------------------------------------------------------------
+    pub fn push( &mut self, task: Task )
+    {
+        self.queue.push_back(task);
+    }
+
+    //--------------------------------------------------------------------------
+    //  Adds tasks at the back of the queue, in order.
+    //--------------------------------------------------------------------------
+    pub fn push_batch( &mut self, tasks: impl IntoIterator<Item = Task> )
+    {
+        self.queue.extend(tasks);
+    }
+
+    //--------------------------------------------------------------------------
+    //  Removes the task at the front of the queue.
+    //--------------------------------------------------------------------------
+    pub fn pop( &mut self ) -> Option<Task>
+    {
+        self.queue.pop_front()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Removes the newest half of the tasks, rounded up, in order.
+    //--------------------------------------------------------------------------
+    pub fn take_half( &mut self ) -> Vec<Task>
+    {
+        let keep = self.queue.len() / 2;
+        self.queue.drain(keep..).collect()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the number of tasks.
+    //--------------------------------------------------------------------------
+    pub fn len( &self ) -> usize
+    {
+        self.queue.len()
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns true if the queue is empty.
+    //--------------------------------------------------------------------------
+    pub fn is_empty( &self ) -> bool
+    {
+        self.queue.is_empty()
+    }
 }
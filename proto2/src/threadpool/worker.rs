@@ -1,52 +1,171 @@
-use super::{ Task, LocalTaskQueue };
+use super::{ Task, Inner, LocalTaskQueue };
+
+use std::cell::{ Cell, RefCell };
+use std::panic::{ catch_unwind, AssertUnwindSafe };
+use std::sync::Arc;
+
+
+//------------------------------------------------------------------------------
+//  A worker checks the global queue before its local queue once every this
+//  many tasks, so that tasks in the global queue are not starved by tasks that
+//  keep rescheduling themselves locally.
+//------------------------------------------------------------------------------
+const GLOBAL_QUEUE_INTERVAL: u32 = 61;
+
+
+//------------------------------------------------------------------------------
+//  Local queue of the worker running on the current thread, with the address
+//  of its pool.
+//------------------------------------------------------------------------------
+thread_local!
+{
+    static CURRENT: RefCell<Option<(usize, Arc<LocalTaskQueue>)>> =
+        const { RefCell::new(None) };
+}
+
+
+//------------------------------------------------------------------------------
+//  Returns the local queue of the current thread if it is a worker of
+//  `inner` .
+//------------------------------------------------------------------------------
+pub(crate) fn current_local_queue
+(
+    inner: &Inner,
+) -> Option<Arc<LocalTaskQueue>>
+{
+    let pool = inner as *const Inner as usize;
+    CURRENT.with(|current|
+    {
+        match &*current.borrow()
+        {
+            Some((current_pool, queue)) if *current_pool == pool =>
+            {
+                Some(queue.clone())
+            },
+            _ => None,
+        }
+    })
+}
+
+
+//------------------------------------------------------------------------------
+//  Records that the worker thread stopped, including when it stops because
+//  the pool shut down.
+//------------------------------------------------------------------------------
+struct StopGuard<'a>(&'a Inner);
+
+impl<'a> Drop for StopGuard<'a>
+{
+    fn drop( &mut self )
+    {
+        CURRENT.with(|current| current.borrow_mut().take());
+        self.0.thread_stopped();
+    }
+}
 
-use std::sync::mpsc::Receiver;
-use std::sync::{ Arc, Mutex };
-use std::time::Duration;
 
 pub(crate) struct Worker
 {
     inner: Arc<Inner>,
-    receiver: Arc<Mutex<Receiver<Task>>>,
+    index: usize,
     local_queue: Arc<LocalTaskQueue>,
+    tick: Cell<u32>,
+    rng: Cell<u32>,
 }
 
 impl Worker
 {
     //--------------------------------------------------------------------------
-    //  Creates a worker.
+    //  Creates a worker that owns the local queue `index` of `inner` .
     //--------------------------------------------------------------------------
-    pub fn new( receiver: Arc<Mutex<Receiver<Task>>> ) -> Self
+    pub fn new( inner: Arc<Inner>, index: usize ) -> Self
     {
+        let local_queue = inner.stealers()[index].queue().clone();
         Self
         {
-            receiver,
-            local_queue: Arc::new(LocalTaskQueue::new()),
+            inner,
+            index,
+            local_queue,
+            tick: Cell::new(0),
+            //  xorshift needs a nonzero seed.
+            rng: Cell::new(index as u32 + 1),
         }
     }
 
     //--------------------------------------------------------------------------
     //  The function to be executed in this worker thread.
+    //
+    //  Runs tasks until the pool shuts down, and parks while there are none.
     //--------------------------------------------------------------------------
     pub(crate) fn work( &self )
     {
-        println!()
-        /*
-        loop
+        let _guard = StopGuard(&self.inner);
+        let pool = Arc::as_ptr(&self.inner) as usize;
+        CURRENT.with(|current|
         {
-            let recv_result = self
-                .receiver
-                .lock()
-                .unwrap()
-                .recv_timeout(Duration::from_millis(500));
+            *current.borrow_mut() = Some((pool, self.local_queue.clone()));
+        });
 
-            match recv_result
+        while !self.inner.is_shutdown()
+        {
+            match self.next_task()
             {
-                Ok(f) =>
+                Some(task) =>
                 {
-                }
+                    //  A panicking task must not stop the worker, which owns
+                    //  a local queue.
+                    let _ = catch_unwind(AssertUnwindSafe(|| task.execute()));
+                },
+                None => self.inner.park(),
             }
         }
-        */
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns the next task to run from the local queue, the global queue or
+    //  the queue of another worker.
+    //--------------------------------------------------------------------------
+    fn next_task( &self ) -> Option<Task>
+    {
+        let tick = self.tick.get().wrapping_add(1);
+        self.tick.set(tick);
+
+        let global_queue = self.inner.global_queue();
+        let task = if tick.is_multiple_of(GLOBAL_QUEUE_INTERVAL)
+        {
+            global_queue.pop().or_else(|| self.local_queue.pop())
+        }
+        else
+        {
+            self.local_queue.pop().or_else(|| global_queue.pop())
+        };
+        task.or_else(|| self.steal())
+    }
+
+    //--------------------------------------------------------------------------
+    //  Steals half of the tasks of another worker, starting from a random
+    //  victim. Returns one of them and keeps the others in the local queue.
+    //--------------------------------------------------------------------------
+    fn steal( &self ) -> Option<Task>
+    {
+        let stealers = self.inner.stealers();
+        let start = self.next_random() as usize % stealers.len();
+        (0..stealers.len())
+            .map(|offset| (start + offset) % stealers.len())
+            .filter(|&victim| victim != self.index)
+            .find_map(|victim| stealers[victim].steal_into(&self.local_queue))
+    }
+
+    //--------------------------------------------------------------------------
+    //  Returns a pseudo-random number to pick victims (xorshift32).
+    //--------------------------------------------------------------------------
+    fn next_random( &self ) -> u32
+    {
+        let mut x = self.rng.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng.set(x);
+        x
     }
 }
@@ -2,11 +2,9 @@ use crate::task::TaskState;
 use crate::threadpool::ThreadPool;
 
 use std::future::Future;
-use std::sync::{ Arc, Mutex };
 use std::marker::Unpin;
 use std::pin::Pin;
 use std::task::Poll;
-use std::sync::mpsc::SyncSender;
 
 struct Executor
 {
@@ -46,34 +44,16 @@ impl Executor
         F: Future<Output = R> + Send + Unpin + 'static,
         R: Send + 'static,
     {
-        struct BlockOnTaskWaker(Mutex<Option<SyncSender<()>>>);
-        impl std::task::Wake for BlockOnTaskWaker
-        {
-            fn wake( self: Arc<Self> )
-            {
-                if let Some(sender) = self.0.lock().unwrap().take()
-                {
-                    let _ = sender.send(());
-                }
-            }
-        }
-
         let (res_sender, res_receiver) = std::sync::mpsc::sync_channel(1);
         self.pool.start_threads().unwrap();
-        self.pool.spawn(move ||
+        self.pool.spawn(move |cx|
         {
-            let (sender, receiver) = std::sync::mpsc::sync_channel(1);
-            let waker = std::task::Waker::from
-            (
-                Arc::new(BlockOnTaskWaker(Mutex::new(Some(sender))))
-            );
-            let mut cx = std::task::Context::from_waker(&waker);
-            if let Poll::Ready(result) = Pin::new(&mut fut).poll(&mut cx)
+            //  The worker polls the future again when `cx` is woken.
+            if let Poll::Ready(result) = Pin::new(&mut fut).poll(cx)
             {
                 res_sender.send(result).unwrap();
                 return TaskState::Done;
             }
-            receiver.recv().unwrap();
             TaskState::Pending
         });
         res_receiver.recv().unwrap()
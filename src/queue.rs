@@ -1,15 +1,21 @@
 use std::collections::BinaryHeap;
-use std::sync::{ Arc, Mutex };
-use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::{ Arc, Condvar, Mutex };
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
 
 
+//------------------------------------------------------------------------------
+//  Creates a priority channel. The receivers get the greatest item first and
+//  block while the channel is empty.
+//------------------------------------------------------------------------------
 pub(crate) fn channel<T: std::cmp::Ord>() -> (Sender<T>, Receiver<T>)
 {
     let inner = Inner
     {
         queue: Mutex::new(BinaryHeap::new()),
-        cnt_sender: AtomicUsize::new(0),
-        cnt_receiver: AtomicUsize::new(0),
+        not_empty: Condvar::new(),
+        closed: AtomicBool::new(false),
+        cnt_sender: AtomicUsize::new(1),
+        cnt_receiver: AtomicUsize::new(1),
     };
     let shared_inner = Arc::new(inner);
 
@@ -22,6 +28,8 @@ pub(crate) fn channel<T: std::cmp::Ord>() -> (Sender<T>, Receiver<T>)
 struct Inner<T>
 {
     queue: Mutex<BinaryHeap<T>>,
+    not_empty: Condvar,
+    closed: AtomicBool,
     cnt_sender: AtomicUsize,
     cnt_receiver: AtomicUsize,
 }
@@ -36,10 +44,37 @@ pub(crate) struct Sender<T>
 
 impl<T: std::cmp::Ord> Sender<T>
 {
+    //--------------------------------------------------------------------------
+    //  Adds `item` to the channel. Drops it if the channel is closed.
+    //--------------------------------------------------------------------------
     pub(crate) fn send( &self, item: T )
     {
         let mut queue = self.inner.queue.lock().unwrap();
+        if self.inner.closed.load(Ordering::SeqCst)
+        {
+            drop(queue);
+            drop(item);
+            return;
+        }
         queue.push(item);
+        drop(queue);
+        self.inner.not_empty.notify_one();
+    }
+
+    //--------------------------------------------------------------------------
+    //  Closes the channel and drops its items. The receivers return `None`
+    //  even though senders are left, and the items sent later are dropped.
+    //--------------------------------------------------------------------------
+    pub(crate) fn close( &self )
+    {
+        let mut queue = self.inner.queue.lock().unwrap();
+        self.inner.closed.store(true, Ordering::SeqCst);
+        let items = std::mem::take(&mut *queue);
+        drop(queue);
+        self.inner.not_empty.notify_all();
+
+        //  Dropped without the lock, as an item may own a sender.
+        drop(items);
     }
 
     pub(crate) fn count( &self ) -> usize
@@ -64,7 +99,13 @@ impl<T> Drop for Sender<T>
 {
     fn drop( &mut self )
     {
-        self.inner.cnt_sender.fetch_sub(1, Ordering::SeqCst);
+        //  Takes the lock so that a receiver cannot miss the last sender
+        //  between checking the count and waiting.
+        let _queue = self.inner.queue.lock();
+        if self.inner.cnt_sender.fetch_sub(1, Ordering::SeqCst) == 1
+        {
+            self.inner.not_empty.notify_all();
+        }
     }
 }
 
@@ -78,10 +119,37 @@ pub(crate) struct Receiver<T>
 
 impl<T: std::cmp::Ord> Receiver<T>
 {
+    //--------------------------------------------------------------------------
+    //  Removes the greatest item, waiting until one is sent. Returns `None`
+    //  once the channel is closed, or empty with all the senders dropped.
+    //--------------------------------------------------------------------------
     pub(crate) fn recv( &self ) -> Option<T>
     {
         let mut queue = self.inner.queue.lock().unwrap();
-        queue.pop()
+        loop
+        {
+            if self.inner.closed.load(Ordering::SeqCst)
+            {
+                return None;
+            }
+            if let Some(item) = queue.pop()
+            {
+                return Some(item);
+            }
+            if self.inner.cnt_sender.load(Ordering::SeqCst) == 0
+            {
+                return None;
+            }
+            queue = self.inner.not_empty.wait(queue).unwrap();
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  Removes the greatest item without waiting.
+    //--------------------------------------------------------------------------
+    pub(crate) fn try_recv( &self ) -> Option<T>
+    {
+        self.inner.queue.lock().unwrap().pop()
     }
 
     pub(crate) fn count( &self ) -> usize
@@ -109,3 +177,38 @@ impl<T> Drop for Receiver<T>
         self.inner.cnt_receiver.fetch_sub(1, Ordering::SeqCst);
     }
 }
+
+#[cfg(test)]
+mod test
+{
+    use super::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn recv_by_priority()
+    {
+        let (sender, receiver) = channel();
+        sender.send(1);
+        sender.send(5);
+        sender.send(3);
+        assert_eq!(receiver.recv(), Some(5));
+        assert_eq!(receiver.recv(), Some(3));
+        assert_eq!(receiver.try_recv(), Some(1));
+        assert_eq!(receiver.try_recv(), None);
+    }
+
+    #[test]
+    fn recv_blocks_until_send()
+    {
+        let (sender, receiver) = channel();
+        let handle = std::thread::spawn(move ||
+        {
+            (receiver.recv(), receiver.recv())
+        });
+
+        std::thread::sleep(Duration::from_millis(50));
+        sender.send(1);
+        drop(sender);
+        assert_eq!(handle.join().unwrap(), (Some(1), None));
+    }
+}
@@ -1,38 +1,163 @@
 use crate::queue::Sender;
 
+use std::collections::HashMap;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::{ Arc, Mutex, Weak };
+use std::task::{ Context, Wake, Waker };
+
 pub(crate) enum TaskState
 {
     Pending,
     Done,
 }
 
+type TaskFn = Box<dyn (FnMut(&mut Context<'_>) -> TaskState) + Send>;
+
 pub(crate) struct Task
 {
-    f: Box<dyn (FnMut() -> TaskState) + Send>,
+    f: TaskFn,
     priority: usize,
 }
 
 impl Task
 {
-    pub(crate) fn new
-    (
-        f: Box<dyn (FnMut() -> TaskState) + Send>,
-        priority: usize,
-    ) -> Self
+    pub(crate) fn new( f: TaskFn, priority: usize ) -> Self
     {
         Self { f, priority }
     }
 
-    pub(crate) fn run( mut self, sender: Sender<Self> )
+    //--------------------------------------------------------------------------
+    //  Runs the task once. If it is pending, the task is kept in `parked`
+    //  and sent back to the queue with the same priority when its waker fires,
+    //  and not before.
+    //--------------------------------------------------------------------------
+    pub(crate) fn run
+    (
+        mut self,
+        sender: Sender<Self>,
+        parked: &Arc<ParkedTasks>,
+    )
+    {
+        let task_waker = Arc::new(TaskWaker
+        {
+            slot: Mutex::new(WakerSlot::Running),
+            id: parked.next_id.fetch_add(1, Ordering::Relaxed),
+            parked: Arc::downgrade(parked),
+            sender,
+        });
+        let waker = Waker::from(task_waker.clone());
+        let mut cx = Context::from_waker(&waker);
+        match (self.f)(&mut cx)
+        {
+            TaskState::Pending => task_waker.park(self),
+            TaskState::Done => {},
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//  Pending tasks waiting for their waker, owned by the pool and its workers
+//  and keyed by the id of their waker. The wakers do not own the tasks,
+//  because a task owns its future, which may own its waker: a task that is
+//  never woken is dropped once the pool is dropped and its workers stop,
+//  instead of leaking.
+//------------------------------------------------------------------------------
+pub(crate) struct ParkedTasks
+{
+    tasks: Mutex<HashMap<usize, Task>>,
+    next_id: AtomicUsize,
+}
+
+impl ParkedTasks
+{
+    pub(crate) fn new() -> Self
+    {
+        Self
+        {
+            tasks: Mutex::new(HashMap::new()),
+            next_id: AtomicUsize::new(0),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//  State of a pending task between its poll and its wakeup.
+//------------------------------------------------------------------------------
+enum WakerSlot
+{
+    //  The task is running and has not been woken.
+    Running,
+
+    //  The task was woken while it was running.
+    Notified,
+
+    //  The task is pending in `ParkedTasks` , waiting for its waker.
+    Parked,
+
+    //  The task has been sent back to the queue.
+    Done,
+}
+
+struct TaskWaker
+{
+    slot: Mutex<WakerSlot>,
+    id: usize,
+    parked: Weak<ParkedTasks>,
+    sender: Sender<Task>,
+}
+
+impl TaskWaker
+{
+    //--------------------------------------------------------------------------
+    //  Keeps `task` in the parked tasks until the waker fires, or sends it
+    //  back immediately if it was woken while running.
+    //--------------------------------------------------------------------------
+    fn park( &self, task: Task )
     {
-        match (self.f)()
+        let mut slot = self.slot.lock().unwrap();
+        match *slot
         {
-            TaskState::Pending =>
+            WakerSlot::Notified =>
             {
-                let task = Self::new(Box::new(self.f), 0);
-                sender.send(task);
+                *slot = WakerSlot::Done;
+                drop(slot);
+                self.sender.send(task);
+            },
+            _ =>
+            {
+                //  Without the pool, nothing can run the task anymore.
+                let Some(parked) = self.parked.upgrade() else { return };
+                parked.tasks.lock().unwrap().insert(self.id, task);
+                *slot = WakerSlot::Parked;
             },
-            TaskState::Done => {},
+        }
+    }
+}
+
+impl Wake for TaskWaker
+{
+    fn wake( self: Arc<Self> )
+    {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref( self: &Arc<Self> )
+    {
+        let mut slot = self.slot.lock().unwrap();
+        match std::mem::replace(&mut *slot, WakerSlot::Done)
+        {
+            WakerSlot::Running => *slot = WakerSlot::Notified,
+            WakerSlot::Parked =>
+            {
+                drop(slot);
+                let Some(parked) = self.parked.upgrade() else { return };
+                let task = parked.tasks.lock().unwrap().remove(&self.id);
+                if let Some(task) = task
+                {
+                    self.sender.send(task);
+                }
+            },
+            WakerSlot::Notified | WakerSlot::Done => {},
         }
     }
 }
@@ -62,3 +187,81 @@ impl std::cmp::PartialEq for Task
 }
 
 impl std::cmp::Eq for Task {}
+
+#[cfg(test)]
+mod test
+{
+    use super::{ ParkedTasks, Task, TaskState };
+    use crate::queue;
+    use crate::threadpool::ThreadPool;
+    use std::sync::Arc;
+    use std::sync::atomic::{ AtomicBool, Ordering };
+    use std::task::Context;
+    use std::time::{ Duration, Instant };
+
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl Drop for DropFlag
+    {
+        fn drop( &mut self )
+        {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    //  Returns the function of a task that owns its waker and is never woken,
+    //  and the flag set when the function is dropped.
+    fn never_woken()
+        -> (impl (FnMut(&mut Context<'_>) -> TaskState) + Send, Arc<AtomicBool>)
+    {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let flag = DropFlag(dropped.clone());
+        let mut wakers = Vec::new();
+        let f = move |cx: &mut Context<'_>|
+        {
+            let _ = &flag;
+            wakers.push(cx.waker().clone());
+            TaskState::Pending
+        };
+        (f, dropped)
+    }
+
+    #[test]
+    fn parked_task_drops_with_pool()
+    {
+        let (f, dropped) = never_woken();
+        let task = Task::new(Box::new(f), 0);
+        let (sender, _receiver) = queue::channel();
+        let parked = Arc::new(ParkedTasks::new());
+        task.run(sender, &parked);
+        assert!(!dropped.load(Ordering::SeqCst));
+
+        drop(parked);
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn parked_task_drops_with_started_pool()
+    {
+        let pool = ThreadPool::new(2, "parked");
+        pool.start_threads().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let (mut f, dropped) = never_woken();
+        pool.spawn(move |cx|
+        {
+            let _ = sender.send(());
+            f(cx)
+        });
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(!dropped.load(Ordering::SeqCst));
+
+        //  The workers stop and drop the parked task with the pool.
+        drop(pool);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !dropped.load(Ordering::SeqCst) && Instant::now() < deadline
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+}
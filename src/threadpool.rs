@@ -1,13 +1,17 @@
-use crate::task::{ Task, TaskState };
+use crate::task::{ ParkedTasks, Task, TaskState };
 use crate::worker::Worker;
 use crate::queue::{ self, Sender, Receiver };
 
+use std::sync::Arc;
+use std::task::Context;
+
 pub(crate) struct ThreadPool
 {
     thread_suffix: &'static str,
     size: usize,
     sender: Sender<Task>,
     receiver: Receiver<Task>,
+    parked: Arc<ParkedTasks>,
 }
 
 impl ThreadPool
@@ -21,13 +25,14 @@ impl ThreadPool
             size,
             sender,
             receiver,
+            parked: Arc::new(ParkedTasks::new()),
         }
     }
 
     pub(crate) fn spawn
     (
         &self,
-        f: impl (FnMut() -> TaskState) + Send + 'static,
+        f: impl (FnMut(&mut Context<'_>) -> TaskState) + Send + 'static,
     )
     {
         let task = Task::new(Box::new(f), 0);
@@ -60,7 +65,12 @@ impl ThreadPool
             now
         );
 
-        let worker = Worker::new(self.sender.clone(), self.receiver.clone());
+        let worker = Worker::new
+        (
+            self.sender.clone(),
+            self.receiver.clone(),
+            self.parked.clone(),
+        );
         std::thread::Builder::new()
             .name(thread_name)
             .spawn(move || worker.work())?;
@@ -70,6 +80,19 @@ impl ThreadPool
 
     fn num_live_thread( &self ) -> usize
     {
-        self.receiver.count()
+        //  Each worker holds a clone of the receiver of the pool.
+        self.receiver.count() - 1
+    }
+}
+
+impl Drop for ThreadPool
+{
+    //--------------------------------------------------------------------------
+    //  Closes the queue, so that the workers stop and drop the parked tasks
+    //  with their last reference.
+    //--------------------------------------------------------------------------
+    fn drop( &mut self )
+    {
+        self.sender.close();
     }
 }
@@ -1,41 +1,40 @@
-use crate::task::Task;
+use crate::task::{ ParkedTasks, Task };
 use crate::queue::{ Sender, Receiver };
 
+use std::sync::Arc;
+
 pub(crate) struct Worker
 {
     queue: Vec<Task>,
     sender: Sender<Task>,
     receiver: Receiver<Task>,
+    parked: Arc<ParkedTasks>,
 }
 
 impl Worker
 {
-    pub(crate) fn new( sender: Sender<Task>, receiver: Receiver<Task> ) -> Self
+    pub(crate) fn new
+    (
+        sender: Sender<Task>,
+        receiver: Receiver<Task>,
+        parked: Arc<ParkedTasks>,
+    ) -> Self
     {
         Self
         {
             queue: Vec::new(),
             sender,
             receiver,
+            parked,
         }
     }
 
     pub(crate) fn work( &self )
     {
-        loop
+        //  Blocks in `recv` while the queue is empty.
+        while let Some(task) = self.receiver.recv()
         {
-            match self.receiver.recv()
-            {
-                Some(task) =>
-                {
-                    println!("{:?}", std::thread::current().name());
-                    task.run(self.sender.clone())
-                },
-                None =>
-                {
-                    std::thread::sleep(std::time::Duration::from_millis(200));
-                },
-            }
+            task.run(self.sender.clone(), &self.parked);
         }
     }
 }